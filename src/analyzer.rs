@@ -0,0 +1,39 @@
+use crate::error::Result;
+
+/// One token of an analyzed sentence, whichever analyzer produced it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Morpheme {
+    pub surface: String,
+    pub reading: String, // hiragana, for the surface form
+    pub lemma: String,
+    pub pos: String,
+    pub sub_pos: String,
+    pub conjugation_type: String,
+    pub conjugation_form: String,
+    pub semantic_info: String,
+    pub normalized_form: Option<String>, // spelling variants unified, e.g. 附属 -> 付属
+    pub canonical_form: Option<CanonicalForm>,
+    pub alternatives: Vec<Morpheme>, // other analyses of the same surface, best first
+}
+
+/// The analyzer's canonical spelling of a lemma with its reading, e.g. the
+/// 代表表記 of Juman++.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CanonicalForm {
+    pub kanji: String,
+    pub reading: String,
+}
+
+/// A morphological analyzer the mining stage can read sentences from.
+///
+/// Analysis happens in two steps so that each can run on its own: `run`
+/// analyzes the sentence file, one sentence per line, into `output_path`, and
+/// `parse` reads that output back.
+pub trait Analyzer {
+    /// Analyzes `sentences_path` into `output_path`.
+    fn run(&self, sentences_path: &str, output_path: &str) -> Result<()>;
+
+    /// Reads the output of `run`, one `Vec` of morphemes per sentence in the
+    /// order of the sentence file. Malformed lines are reported and skipped.
+    fn parse(&self, output_path: &str) -> Result<Vec<Vec<Morpheme>>>;
+}
@@ -0,0 +1,225 @@
+use csv::WriterBuilder;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::epub::Location;
+use crate::error::Result;
+use crate::get_freq::{get_freq_2016, get_freq_anime_jdrama, get_freq_narou};
+use crate::mokuro::Region;
+use crate::subtitle::Cue;
+
+/// A looked up word together with the sentence it was mined from.
+#[derive(Debug)]
+pub struct AnkiExport {
+    pub word: WordInformation,
+    pub sentence: String,
+    pub sentence_furigana: String,
+    pub sentence_number: usize,         // the line of the sentence file
+    pub cue: Option<Cue>,               // where the sentence is said, for subtitles
+    pub region: Option<Region>,         // where the sentence is, for manga
+    pub location: Option<Location>,     // the book and chapter, for epub
+    pub sentence_audio: Option<String>, // media file name, cut from the cue
+    pub picture: Option<String>,        // media file name
+}
+
+/// What the analyzer knows about a looked up word.
+#[derive(Debug)]
+pub struct WordInformation {
+    pub original: String,
+    pub dictionary_form: String,
+    pub reading_kana: String,     // for dictionary form, not original
+    pub reading_furigana: String, // for dictionary form, not original
+    pub pos: String,
+    pub pos_information: String,
+    pub normalized_form: Option<String>,
+}
+
+/// One row of the mining deck, in the field order of the note type.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MiningCard<'a> {
+    pub vocab_kanji: &'a str,
+    pub vocab_kanji_migaku: &'a str,
+    pub vocab_furigana: &'a str,
+    pub vocab_kana: &'a str,
+    pub vocab_def_en: Option<&'a str>,
+    pub vocab_def_ja: Option<&'a str>,
+    pub vocab_audio: Option<&'a str>,
+    pub vocab_pos: &'a str,
+    pub vocab_pos_info: &'a str,
+    pub pitch_accent: Option<u8>,
+    pub picture: Option<&'a str>,
+    pub sentence: &'a str,
+    pub sentence_migaku: &'a str,
+    pub sentence_furigana: &'a str,
+    pub sentence_def: Option<&'a str>,
+    pub sentence_audio: Option<&'a str>,
+    pub hint: Option<&'a str>,
+    pub extra_info: Option<&'a str>,
+    pub kanjified: Option<&'a str>,
+    pub freq_2016_ja: Option<u32>,
+    pub freq_narou: Option<u32>,
+    pub freq_anime_jdrama: Option<u32>,
+    pub freq_netflix: Option<u32>,
+}
+
+/// Looks the word up by its normalized form when the analyzer gave one,
+/// falling back to the dictionary form.
+fn get_freq_normalized<F>(word: &WordInformation, get_freq: F) -> Result<Option<u32>>
+where
+    F: Fn(&str) -> Result<Option<u32>>,
+{
+    if let Some(normalized_form) = &word.normalized_form {
+        if let Some(freq) = get_freq(normalized_form)? {
+            return Ok(Some(freq));
+        }
+    }
+    get_freq(&word.dictionary_form)
+}
+
+/// The field names of the "gd2anki Mining" note type, in `MiningCard` order.
+pub const MINING_FIELDS: [&str; 23] = [
+    "VocabKanji",
+    "VocabKanjiMigaku",
+    "VocabFurigana",
+    "VocabKana",
+    "VocabDefEN",
+    "VocabDefJA",
+    "VocabAudio",
+    "VocabPos",
+    "VocabPosInfo",
+    "PitchAccent",
+    "Picture",
+    "Sentence",
+    "SentenceMigaku",
+    "SentenceFurigana",
+    "SentenceDef",
+    "SentenceAudio",
+    "Hint",
+    "ExtraInfo",
+    "Kanjified",
+    "Freq2016JA",
+    "FreqNarou",
+    "FreqAnimeJdrama",
+    "FreqNetflix",
+];
+
+impl MiningCard<'_> {
+    /// The fields as the note stores them, in `MINING_FIELDS` order, empty
+    /// where the card has nothing.
+    pub fn fields(&self) -> Vec<String> {
+        let text = |s: Option<&str>| s.unwrap_or_default().to_string();
+        let number = |n: Option<u32>| n.map(|n| n.to_string()).unwrap_or_default();
+        vec![
+            self.vocab_kanji.to_string(),
+            self.vocab_kanji_migaku.to_string(),
+            self.vocab_furigana.to_string(),
+            self.vocab_kana.to_string(),
+            text(self.vocab_def_en),
+            text(self.vocab_def_ja),
+            text(self.vocab_audio),
+            self.vocab_pos.to_string(),
+            self.vocab_pos_info.to_string(),
+            number(self.pitch_accent.map(u32::from)),
+            text(self.picture),
+            self.sentence.to_string(),
+            self.sentence_migaku.to_string(),
+            self.sentence_furigana.to_string(),
+            text(self.sentence_def),
+            text(self.sentence_audio),
+            text(self.hint),
+            text(self.extra_info),
+            text(self.kanjified),
+            number(self.freq_2016_ja),
+            number(self.freq_narou),
+            number(self.freq_anime_jdrama),
+            number(self.freq_netflix),
+        ]
+    }
+}
+
+/// Makes the card of each export, with the word in bold and the frequency
+/// fields filled from `freq_db` when given, and hands it to `f`.
+fn for_each_mining_card<F>(v: &[AnkiExport], freq_db: Option<&str>, mut f: F) -> Result<()>
+where
+    F: FnMut(MiningCard) -> Result<()>,
+{
+    for i in v {
+        let re_bold =
+            Regex::new(format!("(?P<kanji>{})", regex::escape(&i.word.original)).as_str())
+                .expect("escaped pattern is always valid");
+        let re_bold_furigana =
+            Regex::new(format!("(?P<kanji>{})", regex::escape(&i.word.reading_furigana)).as_str())
+                .expect("escaped pattern is always valid");
+        let bold_sentence = re_bold.replace_all(&i.sentence, "<b>$kanji</b>");
+        let bold_sentence_furigana =
+            re_bold_furigana.replace_all(&i.sentence_furigana, "<b>$kanji</b>");
+        let (freq_2016_ja, freq_narou, freq_anime_jdrama, freq_netflix) = match freq_db {
+            Some(db) => (
+                get_freq_normalized(&i.word, |word| get_freq_2016(db, word))?,
+                get_freq_normalized(&i.word, |word| {
+                    get_freq_narou(db, word, &i.word.reading_kana)
+                })?,
+                get_freq_normalized(&i.word, |word| {
+                    get_freq_anime_jdrama(db, word, "anime_jdrama")
+                })?,
+                get_freq_normalized(&i.word, |word| get_freq_anime_jdrama(db, word, "netflix"))?,
+            ),
+            None => (None, None, None, None),
+        };
+        let picture = i.picture.as_ref().map(|p| format!("<img src=\"{}\">", p));
+        let extra_info = i.location.as_ref().map(|l| match l.chapter.as_str() {
+            "" => l.book.clone(),
+            chapter => format!("{} — {}", l.book, chapter),
+        });
+        let sentence_audio = i.sentence_audio.as_ref().map(|a| format!("[sound:{}]", a));
+        f(MiningCard {
+            vocab_kanji: &i.word.dictionary_form,
+            vocab_kanji_migaku: &i.word.dictionary_form,
+            vocab_furigana: &i.word.reading_furigana,
+            vocab_kana: &i.word.reading_kana,
+            vocab_def_en: None,
+            vocab_def_ja: None,
+            vocab_audio: None,
+            vocab_pos: &i.word.pos,
+            vocab_pos_info: &i.word.pos_information,
+            pitch_accent: None,
+            picture: picture.as_deref(),
+            sentence: &bold_sentence,
+            sentence_migaku: &bold_sentence,
+            sentence_furigana: &bold_sentence_furigana,
+            sentence_def: None,
+            sentence_audio: sentence_audio.as_deref(),
+            hint: None,
+            extra_info: extra_info.as_deref(),
+            kanjified: None,
+            freq_2016_ja,
+            freq_narou,
+            freq_anime_jdrama,
+            freq_netflix,
+        })?;
+    }
+    Ok(())
+}
+
+/// Renders the exports as the `;` delimited csv Anki imports.
+pub fn make_mining_csv(v: &[AnkiExport], freq_db: Option<&str>) -> Result<String> {
+    let mut data = Vec::new();
+    let mut wtr = WriterBuilder::new()
+        .delimiter(b';')
+        .has_headers(false)
+        .from_writer(&mut data);
+    for_each_mining_card(v, freq_db, |card| Ok(wtr.serialize(card)?))?;
+    wtr.flush().map_err(csv::Error::from)?;
+    drop(wtr);
+    Ok(String::from_utf8(data)?)
+}
+
+/// The fields of each export's note, see `MiningCard::fields`.
+pub fn make_mining_notes(v: &[AnkiExport], freq_db: Option<&str>) -> Result<Vec<Vec<String>>> {
+    let mut notes = Vec::new();
+    for_each_mining_card(v, freq_db, |card| {
+        notes.push(card.fields());
+        Ok(())
+    })?;
+    Ok(notes)
+}
@@ -0,0 +1,386 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use log::warn;
+use serde_json::{json, Map, Value};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use crate::anki::MINING_FIELDS;
+use crate::apkg::{referenced_media, BACK_TEMPLATE, CSS, FRONT_TEMPLATE, MODEL_NAME};
+use crate::error::{Error, Result};
+
+/// Where AnkiConnect listens unless configured otherwise.
+pub const DEFAULT_URL: &str = "http://127.0.0.1:8765";
+
+/// The note type field each sent mining card field goes into, by its index
+/// in `MINING_FIELDS`.
+pub type FieldMap = Vec<(usize, String)>;
+
+/// Every mining card field into the field of the same name, as in the
+/// "gd2anki Mining" note type.
+pub fn default_field_map() -> FieldMap {
+    MINING_FIELDS
+        .iter()
+        .enumerate()
+        .map(|(i, name)| (i, name.to_string()))
+        .collect()
+}
+
+/// Parses a mapping such as `VocabKanji=Word,Sentence=Front`. Mining card
+/// fields left out are not sent, and a field without `=` keeps its name.
+pub fn parse_field_map(spec: &str) -> Result<FieldMap> {
+    let mut map = FieldMap::new();
+    for pair in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (from, to) = pair.split_once('=').unwrap_or((pair, pair));
+        let i = MINING_FIELDS
+            .iter()
+            .position(|&f| f.eq_ignore_ascii_case(from.trim()))
+            .ok_or_else(|| Error::UnknownField(from.trim().to_string()))?;
+        map.push((i, to.trim().to_string()));
+    }
+    Ok(map)
+}
+
+/// What became of one note sent to Anki.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// added as the note with this id
+    Added(i64),
+    /// left out as a duplicate of a note already in the deck
+    Duplicate,
+    /// Anki refused to add it, for this reason
+    Failed(String),
+}
+
+/// Adds the mining cards to a running Anki through the AnkiConnect add-on.
+#[derive(Debug)]
+pub struct AnkiConnect {
+    pub url: String,
+    pub deck: String,
+    pub note_type: String,
+    pub fields: FieldMap,
+    /// where the media the cards refer to is, see `MediaCutter`
+    pub media_dir: Option<String>,
+}
+
+impl AnkiConnect {
+    /// Sends one action and returns its result, or its error as
+    /// `Error::AnkiConnect`.
+    fn request(&self, action: &str, params: Value) -> Result<Value> {
+        let url = &self.url;
+        let response: Value = ureq::post(url)
+            .send_json(json!({ "action": action, "version": 6, "params": params }))
+            .map_err(Error::http(url))?
+            .into_json()
+            .map_err(Error::io(url))?;
+        match response.get("error") {
+            None | Some(Value::Null) => Ok(response.get("result").cloned().unwrap_or_default()),
+            Some(error) => Err(Error::AnkiConnect {
+                action: action.to_string(),
+                error: error
+                    .as_str()
+                    .map_or_else(|| error.to_string(), String::from),
+            }),
+        }
+    }
+
+    fn note(&self, fields: &[String]) -> Value {
+        let fields: Map<String, Value> = self
+            .fields
+            .iter()
+            .map(|(i, name)| {
+                let value = fields.get(*i).cloned().unwrap_or_default();
+                (name.clone(), Value::String(value))
+            })
+            .collect();
+        json!({
+            "deckName": self.deck,
+            "modelName": self.note_type,
+            "fields": fields,
+            "tags": ["gd2anki"],
+            "options": { "allowDuplicate": false, "duplicateScope": "deck" },
+        })
+    }
+
+    /// Creates the "gd2anki Mining" note type when it is the one asked for
+    /// and Anki does not have it yet.
+    fn ensure_note_type(&self) -> Result<()> {
+        let names = self.request("modelNames", json!({}))?;
+        let known = names
+            .as_array()
+            .is_some_and(|names| names.iter().any(|n| n == self.note_type.as_str()));
+        if known {
+            return Ok(());
+        }
+        if self.note_type != MODEL_NAME {
+            return Err(Error::AnkiConnect {
+                action: "modelNames".to_string(),
+                error: format!("there is no note type named {}", self.note_type),
+            });
+        }
+        self.request(
+            "createModel",
+            json!({
+                "modelName": MODEL_NAME,
+                "inOrderFields": MINING_FIELDS,
+                "css": CSS,
+                "isCloze": false,
+                "cardTemplates": [
+                    { "Name": "Mining", "Front": FRONT_TEMPLATE, "Back": BACK_TEMPLATE }
+                ],
+            }),
+        )?;
+        Ok(())
+    }
+
+    fn store_media(&self, name: &str) -> Result<()> {
+        let path = Path::new(self.media_dir.as_deref().unwrap_or(".")).join(name);
+        let path = path.to_string_lossy();
+        let data = fs::read(path.as_ref()).map_err(Error::io(&path))?;
+        self.request(
+            "storeMediaFile",
+            json!({ "filename": name, "data": STANDARD.encode(data) }),
+        )?;
+        Ok(())
+    }
+
+    /// The fields of the note type, in order.
+    fn field_names(&self) -> Result<Vec<String>> {
+        let names = self.request("modelFieldNames", json!({ "modelName": self.note_type }))?;
+        Ok(names
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|name| name.as_str().map(String::from))
+            .collect())
+    }
+
+    /// Adds the notes Anki does not have yet to the deck, with the media
+    /// they refer to, and returns what became of each of them in order.
+    /// Only a failure to talk to Anki at all is an error; once notes have
+    /// been added it fails the rest instead, so the added ones are known.
+    pub fn push(&self, notes: &[Vec<String>]) -> Result<Vec<Outcome>> {
+        self.ensure_note_type()?;
+        let names = self.field_names()?;
+        // AnkiConnect silently leaves out fields the note type does not have
+        let missing: Vec<&str> = self
+            .fields
+            .iter()
+            .map(|(_, name)| name.as_str())
+            .filter(|name| !names.iter().any(|n| n == name))
+            .collect();
+        if !missing.is_empty() {
+            let reason = format!(
+                "the note type {} has no field {}",
+                self.note_type,
+                missing.join(", ")
+            );
+            return Ok(vec![Outcome::Failed(reason); notes.len()]);
+        }
+        self.request("createDeck", json!({ "deck": self.deck }))?;
+
+        let anki_notes: Vec<Value> = notes.iter().map(|note| self.note(note)).collect();
+        let can_add = self.request("canAddNotes", json!({ "notes": anki_notes }))?;
+        let can_add = can_add.as_array().cloned().unwrap_or_default();
+        // canAddNotes also refuses a note whose first field is empty, which
+        // is no duplicate
+        let first = names.first().map_or("", String::as_str);
+        let first_field = self.fields.iter().find(|(_, name)| name == first);
+
+        let mut stored = HashSet::new();
+        let mut outcomes = Vec::new();
+        for (i, (note, anki_note)) in notes.iter().zip(anki_notes).enumerate() {
+            let first_value = first_field.and_then(|(field, _)| note.get(*field));
+            if first_value.is_none_or(|value| value.trim().is_empty()) {
+                outcomes.push(Outcome::Failed(format!(
+                    "the first field {} is empty",
+                    first
+                )));
+                continue;
+            }
+            if can_add.get(i).and_then(Value::as_bool) != Some(true) {
+                outcomes.push(Outcome::Duplicate);
+                continue;
+            }
+            for name in referenced_media(std::slice::from_ref(note)) {
+                if !stored.insert(name.clone()) {
+                    continue;
+                }
+                if let Err(e) = self.store_media(&name) {
+                    warn!("leaving out {}: {}", name, e);
+                }
+            }
+            match self.request("addNote", json!({ "note": anki_note })) {
+                Ok(id) => outcomes.push(Outcome::Added(id.as_i64().unwrap_or_default())),
+                Err(Error::AnkiConnect { error, .. }) => outcomes.push(Outcome::Failed(error)),
+                Err(e) => {
+                    let reason = e.to_string();
+                    outcomes.resize(notes.len(), Outcome::Failed(reason));
+                    break;
+                }
+            }
+        }
+        Ok(outcomes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    /// Serves AnkiConnect requests with `respond`, recording each of them.
+    fn stub_server(respond: fn(&str, &Value) -> Value) -> (String, Arc<Mutex<Vec<Value>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&requests);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut reader = BufReader::new(stream.unwrap());
+                let mut length = 0;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim().is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = header.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            length = value.trim().parse().unwrap();
+                        }
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                let request: Value = serde_json::from_slice(&body).unwrap();
+                let response =
+                    respond(request["action"].as_str().unwrap(), &request["params"]).to_string();
+                recorded.lock().unwrap().push(request);
+                write!(
+                    reader.get_mut(),
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    response.len(),
+                    response
+                )
+                .unwrap();
+            }
+        });
+        (url, requests)
+    }
+
+    fn note(word: &str, audio: &str) -> Vec<String> {
+        let mut note = vec![String::new(); MINING_FIELDS.len()];
+        note[0] = word.to_string();
+        note[15] = audio.to_string();
+        note
+    }
+
+    #[test]
+    fn test_parse_field_map() {
+        assert_eq!(
+            parse_field_map("VocabKanji=Word, sentence=Front,Picture").unwrap(),
+            vec![
+                (0, "Word".to_string()),
+                (11, "Front".to_string()),
+                (10, "Picture".to_string())
+            ]
+        );
+        assert!(matches!(
+            parse_field_map("Meaning=Back"),
+            Err(Error::UnknownField(field)) if field == "Meaning"
+        ));
+    }
+
+    #[test]
+    fn test_push() {
+        let (url, requests) = stub_server(|action, params| match action {
+            "modelNames" => json!({ "result": ["Basic"], "error": null }),
+            "modelFieldNames" => json!({ "result": MINING_FIELDS, "error": null }),
+            "canAddNotes" => json!({ "result": [true, false, true, false], "error": null }),
+            "addNote" if params["note"]["fields"]["VocabKanji"] == "道" => {
+                json!({ "result": null, "error": "cannot create note because it is empty" })
+            }
+            "addNote" => json!({ "result": 1001, "error": null }),
+            _ => json!({ "result": null, "error": null }),
+        });
+        let dir =
+            std::env::temp_dir().join(format!("gd2anki_test_ankiconnect_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("gd2anki_ep01_1000-2000.mp3"), b"mp3").unwrap();
+
+        let client = AnkiConnect {
+            url,
+            deck: "Mining".to_string(),
+            note_type: MODEL_NAME.to_string(),
+            fields: default_field_map(),
+            media_dir: dir.to_str().map(String::from),
+        };
+        let outcomes = client
+            .push(&[
+                note("友達", "[sound:gd2anki_ep01_1000-2000.mp3]"),
+                note("尋ねる", ""),
+                note("道", ""),
+                note("", ""),
+            ])
+            .unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            outcomes,
+            vec![
+                Outcome::Added(1001),
+                Outcome::Duplicate,
+                Outcome::Failed("cannot create note because it is empty".to_string()),
+                Outcome::Failed("the first field VocabKanji is empty".to_string())
+            ]
+        );
+
+        let requests = requests.lock().unwrap();
+        let actions: Vec<&str> = requests
+            .iter()
+            .map(|r| r["action"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            actions,
+            vec![
+                "modelNames",
+                "createModel",
+                "modelFieldNames",
+                "createDeck",
+                "canAddNotes",
+                "storeMediaFile",
+                "addNote",
+                "addNote"
+            ]
+        );
+        assert_eq!(requests[5]["params"]["data"], STANDARD.encode(b"mp3"));
+    }
+
+    #[test]
+    fn test_push_missing_field() {
+        let (url, requests) = stub_server(|action, _| match action {
+            "modelNames" => json!({ "result": ["Basic"], "error": null }),
+            "modelFieldNames" => json!({ "result": ["Front", "Back"], "error": null }),
+            _ => json!({ "result": null, "error": null }),
+        });
+        let client = AnkiConnect {
+            url,
+            deck: "Mining".to_string(),
+            note_type: "Basic".to_string(),
+            fields: parse_field_map("VocabKanji=Front,Sentence=Sentence").unwrap(),
+            media_dir: None,
+        };
+        let outcomes = client.push(&[note("友達", ""), note("道", "")]).unwrap();
+        let reason = "the note type Basic has no field Sentence".to_string();
+        assert_eq!(
+            outcomes,
+            vec![Outcome::Failed(reason.clone()), Outcome::Failed(reason)]
+        );
+        let requests = requests.lock().unwrap();
+        assert!(requests.iter().all(|r| r["action"] != "addNote"));
+    }
+}
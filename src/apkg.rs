@@ -0,0 +1,392 @@
+use log::warn;
+use regex::Regex;
+use rusqlite::{params, Connection};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+use std::sync::LazyLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::anki::MINING_FIELDS;
+use crate::error::{Error, Result};
+
+/// The id of the "gd2anki Mining" note type. It never changes, so that every
+/// deck written adds to the same note type instead of a copy of it.
+const MODEL_ID: i64 = 1_602_424_200_000;
+
+pub const MODEL_NAME: &str = "gd2anki Mining";
+
+pub const FRONT_TEMPLATE: &str = r#"<div class="vocab">{{VocabKanji}}</div>
+<div class="sentence">{{Sentence}}</div>
+{{#Picture}}<div class="picture">{{Picture}}</div>{{/Picture}}"#;
+
+pub const BACK_TEMPLATE: &str = r#"<div class="vocab">{{furigana:VocabFurigana}}</div>
+<div class="sentence">{{furigana:SentenceFurigana}}</div>
+{{#Picture}}<div class="picture">{{Picture}}</div>{{/Picture}}
+<hr id="answer">
+{{SentenceAudio}}
+<div class="pos">{{VocabPos}} {{VocabPosInfo}}</div>
+{{#ExtraInfo}}<div class="extra">{{ExtraInfo}}</div>{{/ExtraInfo}}
+<div class="freq">{{#Freq2016JA}}2016: {{Freq2016JA}} {{/Freq2016JA}}{{#FreqNarou}}narou: {{FreqNarou}} {{/FreqNarou}}{{#FreqAnimeJdrama}}anime: {{FreqAnimeJdrama}} {{/FreqAnimeJdrama}}{{#FreqNetflix}}netflix: {{FreqNetflix}}{{/FreqNetflix}}</div>"#;
+
+pub const CSS: &str = ".card { font-family: sans-serif; font-size: 24px; text-align: center; }
+.vocab { font-size: 48px; }
+.sentence b { color: #e0a030; }
+.picture img { max-width: 100%; max-height: 50vh; }
+.pos, .extra, .freq { font-size: 14px; color: gray; }";
+
+/// The schema of a legacy `collection.anki2`, which every Anki version still
+/// imports.
+const SCHEMA: &str = "
+CREATE TABLE col (id integer PRIMARY KEY, crt integer NOT NULL, mod integer NOT NULL,
+    scm integer NOT NULL, ver integer NOT NULL, dty integer NOT NULL, usn integer NOT NULL,
+    ls integer NOT NULL, conf text NOT NULL, models text NOT NULL, decks text NOT NULL,
+    dconf text NOT NULL, tags text NOT NULL);
+CREATE TABLE notes (id integer PRIMARY KEY, guid text NOT NULL, mid integer NOT NULL,
+    mod integer NOT NULL, usn integer NOT NULL, tags text NOT NULL, flds text NOT NULL,
+    sfld integer NOT NULL, csum integer NOT NULL, flags integer NOT NULL, data text NOT NULL);
+CREATE TABLE cards (id integer PRIMARY KEY, nid integer NOT NULL, did integer NOT NULL,
+    ord integer NOT NULL, mod integer NOT NULL, usn integer NOT NULL, type integer NOT NULL,
+    queue integer NOT NULL, due integer NOT NULL, ivl integer NOT NULL,
+    factor integer NOT NULL, reps integer NOT NULL, lapses integer NOT NULL,
+    left integer NOT NULL, odue integer NOT NULL, odid integer NOT NULL,
+    flags integer NOT NULL, data text NOT NULL);
+CREATE TABLE revlog (id integer PRIMARY KEY, cid integer NOT NULL, usn integer NOT NULL,
+    ease integer NOT NULL, ivl integer NOT NULL, lastIvl integer NOT NULL,
+    factor integer NOT NULL, time integer NOT NULL, type integer NOT NULL);
+CREATE TABLE graves (usn integer NOT NULL, oid integer NOT NULL, type integer NOT NULL);
+CREATE INDEX ix_notes_usn ON notes (usn);
+CREATE INDEX ix_cards_usn ON cards (usn);
+CREATE INDEX ix_revlog_usn ON revlog (usn);
+CREATE INDEX ix_cards_nid ON cards (nid);
+CREATE INDEX ix_cards_sched ON cards (did, queue, due);
+CREATE INDEX ix_revlog_cid ON revlog (cid);
+CREATE INDEX ix_notes_csum ON notes (csum);
+";
+
+fn sha1(text: &str) -> [u8; 20] {
+    sha1_smol::Sha1::from(text).digest().bytes()
+}
+
+/// A stable id for `name` below 2^53, so that the same deck name always
+/// imports into the same deck.
+fn deck_id(name: &str) -> i64 {
+    let hash = sha1(name);
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&hash[..8]);
+    (u64::from_be_bytes(bytes) >> 12) as i64
+}
+
+/// Anki's checksum of the sort field, the first 8 hex digits of its sha1.
+fn checksum(sort_field: &str) -> i64 {
+    let hash = sha1(sort_field);
+    i64::from(u32::from_be_bytes([hash[0], hash[1], hash[2], hash[3]]))
+}
+
+static RE_TAG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<[^>]*>").unwrap());
+static RE_MEDIA: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"\[sound:([^\]]+)\]|<img[^>]*\bsrc="([^"]+)""#).unwrap());
+
+fn strip_html(text: &str) -> String {
+    RE_TAG.replace_all(text, "").into_owned()
+}
+
+fn field<'a>(note: &'a [String], name: &str) -> &'a str {
+    MINING_FIELDS
+        .iter()
+        .position(|&f| f == name)
+        .and_then(|i| note.get(i))
+        .map_or("", String::as_str)
+}
+
+/// The note's guid, from the word and the sentence. Importing the same card
+/// again updates the note instead of adding a duplicate.
+fn guid(note: &[String]) -> String {
+    let key = format!(
+        "{}\x1f{}",
+        field(note, "VocabKanji"),
+        strip_html(field(note, "Sentence"))
+    );
+    sha1(&key)[..8]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// The media files the fields refer to, by `[sound:...]` or `<img src="...">`.
+pub fn referenced_media(notes: &[Vec<String>]) -> Vec<String> {
+    let mut names: Vec<String> = notes
+        .iter()
+        .flatten()
+        .flat_map(|f| RE_MEDIA.captures_iter(f))
+        .filter_map(|caps| Some(caps.get(1).or_else(|| caps.get(2))?.as_str().to_string()))
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+fn model(deck_id: i64, now: i64) -> serde_json::Value {
+    let fields: Vec<_> = MINING_FIELDS
+        .iter()
+        .enumerate()
+        .map(|(ord, name)| {
+            json!({
+                "name": name, "ord": ord, "sticky": false, "rtl": false,
+                "font": "Arial", "size": 20, "media": [],
+            })
+        })
+        .collect();
+    json!({
+        "id": MODEL_ID, "name": MODEL_NAME, "type": 0, "mod": now, "usn": -1,
+        "sortf": 0, "did": deck_id, "flds": fields,
+        "tmpls": [{
+            "name": "Mining", "ord": 0, "qfmt": FRONT_TEMPLATE, "afmt": BACK_TEMPLATE,
+            "bqfmt": "", "bafmt": "", "did": null, "bfont": "", "bsize": 0,
+        }],
+        "css": CSS,
+        "latexPre": "\\documentclass[12pt]{article}\n\\special{papersize=3in,5in}\n\\usepackage[utf8]{inputenc}\n\\usepackage{amssymb,amsmath}\n\\pagestyle{empty}\n\\setlength{\\parindent}{0in}\n\\begin{document}\n",
+        "latexPost": "\\end{document}",
+        "latexsvg": false,
+        "req": [[0, "any", [0]]],
+        "tags": [], "vers": [],
+    })
+}
+
+fn deck(id: i64, name: &str, now: i64) -> serde_json::Value {
+    json!({
+        "id": id, "name": name, "mod": now, "usn": -1, "desc": "", "dyn": 0, "conf": 1,
+        "collapsed": false, "browserCollapsed": false, "extendNew": 0, "extendRev": 0,
+        "newToday": [0, 0], "revToday": [0, 0], "lrnToday": [0, 0], "timeToday": [0, 0],
+    })
+}
+
+/// Anki's default options group, which the deck uses.
+fn deck_config(now: i64) -> serde_json::Value {
+    json!({
+        "1": {
+            "id": 1, "name": "Default", "mod": now, "usn": -1, "maxTaken": 60,
+            "autoplay": true, "timer": 0, "replayq": true, "dyn": false,
+            "new": {
+                "bury": true, "delays": [1.0, 10.0], "initialFactor": 2500,
+                "ints": [1, 4, 7], "order": 1, "perDay": 20, "separate": true,
+            },
+            "rev": {
+                "bury": true, "ease4": 1.3, "fuzz": 0.05, "ivlFct": 1.0,
+                "maxIvl": 36500, "minSpace": 1, "perDay": 200,
+            },
+            "lapse": {
+                "delays": [10.0], "leechAction": 0, "leechFails": 8, "minInt": 1, "mult": 0.0,
+            },
+        }
+    })
+}
+
+/// Creates the collection holding the notes in `conn`, one new card each in
+/// the deck named `deck_name`, due in the order given.
+fn write_collection(
+    conn: &Connection,
+    path: &str,
+    deck_name: &str,
+    notes: &[Vec<String>],
+) -> Result<()> {
+    let now_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as i64);
+    let now = now_ms / 1000;
+    let did = deck_id(deck_name);
+
+    let conf = json!({
+        "nextPos": notes.len() + 1, "estTimes": true, "activeDecks": [did], "curDeck": did,
+        "sortType": "noteFld", "timeLim": 0, "sortBackwards": false, "addToCur": true,
+        "newBury": true, "newSpread": 0, "dueCounts": true, "curModel": MODEL_ID,
+        "collapseTime": 1200,
+    });
+    let models = json!({ MODEL_ID.to_string(): model(did, now) });
+    let decks = json!({
+        "1": deck(1, "Default", now),
+        did.to_string(): deck(did, deck_name, now),
+    });
+
+    conn.execute_batch(SCHEMA).map_err(Error::database(path))?;
+    conn.execute(
+        "INSERT INTO col VALUES (1, ?1, ?2, ?2, 11, 0, 0, 0, ?3, ?4, ?5, ?6, '{}')",
+        params![
+            now - now % 86400,
+            now_ms,
+            conf.to_string(),
+            models.to_string(),
+            decks.to_string(),
+            deck_config(now).to_string()
+        ],
+    )
+    .map_err(Error::database(path))?;
+
+    for (i, note) in notes.iter().enumerate() {
+        let id = now_ms + i as i64;
+        let sort_field = strip_html(note.first().map_or("", String::as_str));
+        conn.execute(
+            "INSERT INTO notes VALUES (?1, ?2, ?3, ?4, -1, ' gd2anki ', ?5, ?6, ?7, 0, '')",
+            params![
+                id,
+                guid(note),
+                MODEL_ID,
+                now,
+                note.join("\x1f"),
+                sort_field,
+                checksum(&sort_field)
+            ],
+        )
+        .map_err(Error::database(path))?;
+        conn.execute(
+            "INSERT INTO cards VALUES (?1, ?1, ?2, 0, ?3, -1, 0, 0, ?4, 0, 0, 0, 0, 0, 0, 0, 0, '')",
+            params![id, did, now, i as i64 + 1],
+        )
+        .map_err(Error::database(path))?;
+    }
+    Ok(())
+}
+
+/// Writes the notes as an `.apkg` Anki imports on opening, with the
+/// "gd2anki Mining" note type, a deck named `deck_name` and the media the
+/// notes refer to, taken from `media_dir`.
+pub fn write_apkg(
+    path: &str,
+    deck_name: &str,
+    notes: &[Vec<String>],
+    media_dir: Option<&str>,
+) -> Result<()> {
+    // the collection is written next to the package, then zipped into it
+    let collection_path = format!("{}.anki2", path);
+    let _ = fs::remove_file(&collection_path);
+    let conn = Connection::open(&collection_path).map_err(Error::database(&collection_path))?;
+    let written = write_collection(&conn, &collection_path, deck_name, notes);
+    drop(conn);
+    let collection =
+        written.and_then(|_| fs::read(&collection_path).map_err(Error::io(&collection_path)));
+    let _ = fs::remove_file(&collection_path);
+    let collection = collection?;
+
+    let file = File::create(path).map_err(Error::io(path))?;
+    let mut zip = ZipWriter::new(file);
+    zip.start_file("collection.anki2", FileOptions::default())
+        .map_err(Error::zip(path))?;
+    zip.write_all(&collection).map_err(Error::io(path))?;
+
+    // audio and pictures are compressed already
+    let stored = FileOptions::default().compression_method(CompressionMethod::Stored);
+    let mut manifest = BTreeMap::new();
+    for name in referenced_media(notes) {
+        let media_path = Path::new(media_dir.unwrap_or(".")).join(&name);
+        let data = match fs::read(&media_path) {
+            Ok(data) => data,
+            Err(e) => {
+                warn!("leaving out {}: {}", media_path.display(), e);
+                continue;
+            }
+        };
+        let entry = manifest.len().to_string();
+        zip.start_file(entry.as_str(), stored)
+            .map_err(Error::zip(path))?;
+        zip.write_all(&data).map_err(Error::io(path))?;
+        manifest.insert(entry, name);
+    }
+
+    zip.start_file("media", FileOptions::default())
+        .map_err(Error::zip(path))?;
+    let manifest = serde_json::to_string(&manifest).map_err(Error::json(path))?;
+    zip.write_all(manifest.as_bytes())
+        .map_err(Error::io(path))?;
+    zip.finish().map_err(Error::zip(path))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::NO_PARAMS;
+    use std::collections::HashMap;
+    use std::io::Read;
+
+    fn note(word: &str, sentence: &str, audio: &str, picture: &str) -> Vec<String> {
+        let mut note = vec![String::new(); MINING_FIELDS.len()];
+        note[0] = word.to_string();
+        note[10] = picture.to_string();
+        note[11] = sentence.to_string();
+        note[15] = audio.to_string();
+        note
+    }
+
+    #[test]
+    fn test_write_apkg() {
+        let dir = std::env::temp_dir().join(format!("gd2anki_test_apkg_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("gd2anki_ep01_1000-2000.mp3"), b"mp3").unwrap();
+        let notes = vec![
+            note(
+                "友達",
+                "<b>友達</b>に会った",
+                "[sound:gd2anki_ep01_1000-2000.mp3]",
+                "<img src=\"missing.jpg\">",
+            ),
+            note("尋ねる", "道を<b>尋ねた</b>", "", ""),
+        ];
+        let path = dir.join("mining.apkg");
+        let path = path.to_str().unwrap();
+        write_apkg(path, "日本語::Mining", &notes, dir.to_str()).unwrap();
+
+        let mut zip = zip::ZipArchive::new(File::open(path).unwrap()).unwrap();
+        let mut manifest = String::new();
+        zip.by_name("media")
+            .unwrap()
+            .read_to_string(&mut manifest)
+            .unwrap();
+        let manifest: HashMap<String, String> = serde_json::from_str(&manifest).unwrap();
+        assert_eq!(manifest.len(), 1);
+        assert_eq!(manifest["0"], "gd2anki_ep01_1000-2000.mp3");
+        assert!(zip.by_name("0").is_ok());
+
+        let mut collection = Vec::new();
+        zip.by_name("collection.anki2")
+            .unwrap()
+            .read_to_end(&mut collection)
+            .unwrap();
+        let collection_path = dir.join("collection.anki2");
+        fs::write(&collection_path, collection).unwrap();
+        let conn = Connection::open(&collection_path).unwrap();
+        let (sfld, csum, flds): (String, i64, String) = conn
+            .query_row(
+                "SELECT sfld, csum, flds FROM notes ORDER BY id LIMIT 1",
+                NO_PARAMS,
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(sfld, "友達");
+        assert_eq!(csum, checksum("友達"));
+        assert_eq!(flds.split('\x1f').count(), MINING_FIELDS.len());
+        let dues: Vec<i64> = conn
+            .prepare("SELECT due FROM cards WHERE did = ?1 ORDER BY due")
+            .unwrap()
+            .query_map(params![deck_id("日本語::Mining")], |row| row.get(0))
+            .unwrap()
+            .map(|due| due.unwrap())
+            .collect();
+        assert_eq!(dues, vec![1, 2]);
+        let models: String = conn
+            .query_row("SELECT models FROM col", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert!(models.contains(MODEL_NAME));
+        drop(conn);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_checksum() {
+        // the first 8 hex digits of sha1("test"), a94a8fe5...
+        assert_eq!(checksum("test"), 0xa94a_8fe5);
+    }
+}
@@ -0,0 +1,109 @@
+use lindera_core::mode::Mode;
+use lindera_dictionary::{DictionaryConfig, DictionaryKind};
+use lindera_tokenizer::tokenizer::{Tokenizer, TokenizerConfig};
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+
+use crate::analyzer::{Analyzer, Morpheme};
+use crate::error::{Error, Result};
+use crate::mecab::{parse_mecab_output, MecabDictionary};
+
+/// The features MeCab gives a word IPADIC does not know.
+const UNKNOWN_FEATURES: &str = "名詞,一般,*,*,*,*,*";
+
+/// A pure Rust tokenizer, so that no analyzer binary has to be installed.
+///
+/// It runs lindera with the IPADIC dictionary compiled into the binary and
+/// writes the same output as `mecab` would with IPADIC.
+#[derive(Debug)]
+pub struct Builtin;
+
+/// Splits each line of `text` into its surfaces and IPADIC features.
+pub fn tokenize(text: &str) -> Result<Vec<Vec<(String, String)>>> {
+    let tokenizer = Tokenizer::from_config(TokenizerConfig {
+        dictionary: DictionaryConfig {
+            kind: Some(DictionaryKind::IPADIC),
+            path: None,
+        },
+        user_dictionary: None,
+        mode: Mode::Normal,
+    })?;
+    let mut ret = Vec::new();
+    for line in text.lines() {
+        let mut sentence = Vec::new();
+        for mut token in tokenizer.tokenize(line)? {
+            let features = match token.get_details() {
+                Some(details) if details.len() > 1 => details.join(","),
+                _ => UNKNOWN_FEATURES.to_string(),
+            };
+            sentence.push((token.text.to_string(), features));
+        }
+        ret.push(sentence);
+    }
+    Ok(ret)
+}
+
+impl Analyzer for Builtin {
+    fn run(&self, sentences_path: &str, output_path: &str) -> Result<()> {
+        let sentences = fs::read_to_string(sentences_path).map_err(Error::io(sentences_path))?;
+
+        let mut output = String::new();
+        for sentence in tokenize(&sentences)? {
+            for (surface, features) in sentence {
+                output += format!("{}\t{}\n", surface, features).as_str();
+            }
+            output += "EOS\n";
+        }
+
+        let mut output_file = OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .create(true)
+            .open(output_path)
+            .map_err(Error::io(output_path))?;
+        output_file
+            .write_all(output.as_bytes())
+            .map_err(Error::io(output_path))?;
+        Ok(())
+    }
+
+    fn parse(&self, output_path: &str) -> Result<Vec<Vec<Morpheme>>> {
+        parse_mecab_output(output_path, MecabDictionary::Ipadic)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize() {
+        let sentences = tokenize("友達に道を尋ねた。\nはい").unwrap();
+        let surfaces: Vec<&str> = sentences[0].iter().map(|(s, _)| s.as_str()).collect();
+        assert_eq!(surfaces, vec!["友達", "に", "道", "を", "尋ね", "た", "。"]);
+        assert_eq!(
+            sentences[0][4].1,
+            "動詞,自立,*,*,一段,連用形,尋ねる,タズネ,タズネ"
+        );
+        assert_eq!(sentences.len(), 2);
+    }
+
+    #[test]
+    fn test_run() {
+        let dir = std::env::temp_dir().join(format!("gd2anki_test_builtin_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let sentences_path = dir.join("sentences.txt");
+        let output_path = dir.join("output.txt");
+        let sentences_path = sentences_path.to_str().unwrap();
+        let output_path = output_path.to_str().unwrap();
+        fs::write(sentences_path, "道を尋ねた\n").unwrap();
+
+        Builtin.run(sentences_path, output_path).unwrap();
+        let morphemes = Builtin.parse(output_path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(morphemes.len(), 1);
+        assert_eq!(morphemes[0][2].lemma, "尋ねる");
+        assert_eq!(morphemes[0][2].reading, "たずね");
+    }
+}
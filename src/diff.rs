@@ -0,0 +1,60 @@
+use colored::Colorize;
+use difference::{Changeset, Difference};
+use log::warn;
+use regex::Regex;
+use std::fs;
+
+use crate::error::{Error, Result};
+
+fn easy_counter_diff(file_path: &str) -> Result<String> {
+    let s = fs::read_to_string(file_path).map_err(Error::io(file_path))?;
+    let mut current_sentence_count = 0;
+    let mut ret = String::new();
+
+    let re = Regex::new(r"(\d+)").unwrap();
+    let mut count = 0;
+    for (line, x) in s.lines().enumerate() {
+        let sentence_count = match re.captures(x).map(|cap| cap[1].parse::<i32>()) {
+            Some(Ok(sentence_count)) => sentence_count,
+            _ => {
+                warn!(
+                    "skipping {}",
+                    Error::line(file_path, line + 1, x, "no sentence number")
+                );
+                continue;
+            }
+        };
+        if sentence_count == current_sentence_count {
+            count += 1;
+        } else {
+            ret += format!("{:04} {}\n", current_sentence_count, count).as_str();
+            count = 0;
+            current_sentence_count += 1;
+        }
+    }
+    Ok(format!("{}{:04} {}", ret, current_sentence_count, count))
+}
+
+/// Compares how many words each sentence got in two numbered word lists,
+/// e.g. `vocabs.txt` against the `result.txt` written by
+/// `mining::mine_sentences`.
+pub fn get_diff(original: &str, output: &str) -> Result<String> {
+    let s1 = easy_counter_diff(original)?;
+    let s2 = easy_counter_diff(output)?;
+
+    let changeset = Changeset::new(&s1, &s2, "");
+    let mut ret_text = String::new();
+    for x in changeset.diffs.iter() {
+        if let Difference::Same(text) = x {
+            let mut tmp_vec: Vec<&str> = text.split('\n').collect();
+            if let Some(sentence_count) = tmp_vec.pop() {
+                ret_text += sentence_count;
+            }
+        } else if let Difference::Rem(text) = x {
+            ret_text = format!("{}{}", ret_text, text.red());
+        } else if let Difference::Add(text) = x {
+            ret_text = format!("{}{}\n", ret_text, text.green());
+        }
+    }
+    Ok(ret_text)
+}
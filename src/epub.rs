@@ -0,0 +1,375 @@
+use log::warn;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use regex::Regex;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Seek};
+use std::path::Path;
+use zip::ZipArchive;
+
+use crate::error::{Error, Result};
+use crate::gdict::Vocab;
+use crate::pairing::{find_containing, read_numbered, write_numbered, Decision, Pairing};
+use crate::ruby::{parse_html_ruby, split_sentences, ReadingHints};
+
+/// One xhtml file of the spine of a book.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chapter {
+    pub href: String,
+    /// its first heading, otherwise its `<title>`
+    pub title: Option<String>,
+}
+
+/// The text of an EPUB book in reading order.
+#[derive(Debug, Default)]
+pub struct Book {
+    pub title: Option<String>,
+    pub chapters: Vec<Chapter>,
+    /// each sentence with the index of its chapter
+    pub sentences: Vec<(usize, String)>,
+    /// the readings of the book's ruby
+    pub hints: ReadingHints,
+}
+
+/// Which book and chapter a sentence is from, the chapter being empty when
+/// unknown.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    pub book: String,
+    pub chapter: String,
+}
+
+/// The manifest and spine of the OPF package document.
+#[derive(Debug, Default)]
+struct Package {
+    title: Option<String>,
+    manifest: HashMap<String, String>,
+    spine: Vec<String>,
+}
+
+fn attribute(e: &BytesStart, name: &[u8]) -> Option<String> {
+    e.attributes()
+        .flatten()
+        .find(|a| a.key == name)
+        .and_then(|a| {
+            a.unescaped_value()
+                .ok()
+                .map(|v| String::from_utf8_lossy(&v).into_owned())
+        })
+}
+
+/// Finds the path of the OPF package document in `META-INF/container.xml`.
+fn parse_container(xml: &str, path: &str) -> Result<Option<String>> {
+    let mut reader = Reader::from_str(xml);
+    let mut buf = Vec::new();
+    loop {
+        match reader.read_event(&mut buf).map_err(Error::xml(path))? {
+            Event::Start(e) | Event::Empty(e) if e.local_name() == b"rootfile" => {
+                return Ok(attribute(&e, b"full-path"))
+            }
+            Event::Eof => return Ok(None),
+            _ => {}
+        }
+        buf.clear();
+    }
+}
+
+fn parse_package(xml: &str, path: &str) -> Result<Package> {
+    let mut reader = Reader::from_str(xml);
+    reader.trim_text(true);
+    let mut buf = Vec::new();
+    let mut package = Package::default();
+    let mut in_title = false;
+    loop {
+        match reader.read_event(&mut buf).map_err(Error::xml(path))? {
+            Event::Start(e) | Event::Empty(e) => match e.local_name() {
+                b"item" => {
+                    if let (Some(id), Some(href)) = (attribute(&e, b"id"), attribute(&e, b"href")) {
+                        package.manifest.insert(id, href);
+                    }
+                }
+                b"itemref" => package.spine.extend(attribute(&e, b"idref")),
+                b"title" => in_title = package.title.is_none(),
+                _ => {}
+            },
+            Event::Text(e) if in_title => {
+                package.title = Some(e.unescape_and_decode(&reader).map_err(Error::xml(path))?);
+            }
+            Event::End(_) => in_title = false,
+            Event::Eof => return Ok(package),
+            _ => {}
+        }
+        buf.clear();
+    }
+}
+
+/// Resolves the percent encoded `href` against the directory `base`, both
+/// inside the archive.
+fn resolve(base: &str, href: &str) -> String {
+    let href = href.split('#').next().unwrap_or_default();
+    let mut bytes = Vec::new();
+    let mut rest = href.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        let escaped = match tail {
+            [h, l, ..] if b == b'%' => std::str::from_utf8(&[*h, *l])
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+            _ => None,
+        };
+        match escaped {
+            Some(b) => {
+                bytes.push(b);
+                rest = &tail[2..];
+            }
+            None => {
+                bytes.push(b);
+                rest = tail;
+            }
+        }
+    }
+
+    let mut parts: Vec<&str> = base.split('/').filter(|p| !p.is_empty()).collect();
+    let href = String::from_utf8_lossy(&bytes);
+    for part in href.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+/// Splits the body of an xhtml chapter into sentences, adding the readings of
+/// its ruby to `hints`, and returns them with the chapter's title.
+pub fn parse_xhtml(xhtml: &str, hints: &mut ReadingHints) -> (Option<String>, Vec<String>) {
+    let re_body = Regex::new(r"(?is)<body[^>]*>(.*)</body>").unwrap();
+    let re_heading = Regex::new(r"(?is)<h[1-6][^>]*>(.*?)</h[1-6]>").unwrap();
+    let re_title = Regex::new(r"(?is)<title[^>]*>(.*?)</title>").unwrap();
+    let re_block = Regex::new(r"(?i)<br[^>]*>|</(p|div|h[1-6]|li|blockquote|tr|section)>").unwrap();
+
+    let body = re_body
+        .captures(xhtml)
+        .map_or(xhtml, |c| c.get(1).unwrap().as_str());
+    // line breaks in the markup mean nothing, the block elements do
+    let body = body.replace(&['\r', '\n'][..], "");
+
+    let clean =
+        |html: &str, hints: &mut ReadingHints| parse_html_ruby(html, hints).trim().to_string();
+    let title = re_heading
+        .captures(&body)
+        .or_else(|| re_title.captures(xhtml))
+        .map(|c| clean(&c[1], &mut ReadingHints::new()))
+        .filter(|t| !t.is_empty());
+    let sentences = re_block
+        .replace_all(&body, "\n")
+        .lines()
+        .flat_map(|paragraph| split_sentences(&clean(paragraph, hints)))
+        .collect();
+    (title, sentences)
+}
+
+/// Reads the chapters of an EPUB from `reader`, `path` naming it in errors.
+pub fn read_epub_from<R: Read + Seek>(reader: R, path: &str) -> Result<Book> {
+    let mut archive = ZipArchive::new(reader).map_err(Error::zip(path))?;
+    let mut read_entry = |name: &str| -> Result<String> {
+        let mut text = String::new();
+        archive
+            .by_name(name)
+            .map_err(Error::zip(path))?
+            .read_to_string(&mut text)
+            .map_err(Error::io(path))?;
+        Ok(text)
+    };
+
+    let opf_path =
+        parse_container(&read_entry("META-INF/container.xml")?, path)?.ok_or_else(|| {
+            let e = io::Error::new(io::ErrorKind::InvalidData, "no rootfile in the container");
+            Error::io(path)(e)
+        })?;
+    let package = parse_package(&read_entry(&opf_path)?, path)?;
+    let base = opf_path.rsplit_once('/').map_or("", |(dir, _)| dir);
+
+    let mut book = Book {
+        title: package.title,
+        ..Book::default()
+    };
+    for idref in &package.spine {
+        let href = match package.manifest.get(idref) {
+            Some(href) => resolve(base, href),
+            None => {
+                warn!("{}: the spine refers to a missing item {}", path, idref);
+                continue;
+            }
+        };
+        let xhtml = match read_entry(&href) {
+            Ok(xhtml) => xhtml,
+            Err(e) => {
+                warn!("skipping the chapter {}: {}", href, e);
+                continue;
+            }
+        };
+        let (title, sentences) = parse_xhtml(&xhtml, &mut book.hints);
+        let chapter = book.chapters.len();
+        book.chapters.push(Chapter { href, title });
+        book.sentences
+            .extend(sentences.into_iter().map(|s| (chapter, s)));
+    }
+    Ok(book)
+}
+
+/// Reads the chapters of the EPUB at `path`.
+pub fn read_epub(path: &str) -> Result<Book> {
+    let file = File::open(path).map_err(Error::io(path))?;
+    read_epub_from(file, path)
+}
+
+/// Pairs each of `words` with the first sentence of `book` containing it, and
+/// returns the location of every sentence by its number. `name` stands for
+/// a book without a title.
+pub fn pair_book(book: &Book, name: &str, words: &[String]) -> (Pairing, HashMap<usize, Location>) {
+    let mut pairing = Pairing::default();
+    let mut locations = HashMap::new();
+    let book_title = book.title.as_deref().unwrap_or(name);
+
+    for word in words.iter().map(|w| w.trim()).filter(|w| !w.is_empty()) {
+        let (chapter, sentence) = match find_containing(&book.sentences, word, |s| &s.1) {
+            Some(found) => found,
+            None => {
+                pairing.decisions.push(Decision::Dropped {
+                    word: word.to_string(),
+                    reason: format!("not in {}", book_title),
+                });
+                continue;
+            }
+        };
+        let chapter = &book.chapters[*chapter];
+        let chapter = chapter.title.as_ref().unwrap_or(&chapter.href);
+        let vocab = Vocab {
+            kanji: word.to_string(),
+            sentence: sentence.clone(),
+        };
+        let number = pairing.add_pair(&vocab, &format!("found in {}", chapter));
+        locations.entry(number).or_insert_with(|| Location {
+            book: book_title.to_string(),
+            chapter: chapter.clone(),
+        });
+    }
+
+    (pairing, locations)
+}
+
+/// Writes the locations as `0012<TAB>book<TAB>chapter` lines.
+pub fn write_locations(path: &str, locations: &HashMap<usize, Location>) -> Result<()> {
+    write_numbered(path, locations, |location| {
+        vec![location.book.clone(), location.chapter.clone()]
+    })
+}
+
+/// Reads the locations written by `write_locations`.
+pub fn read_locations(path: &str) -> Result<HashMap<usize, Location>> {
+    read_numbered(path, 2, "location", |v| {
+        Some(Location {
+            book: v[0].to_string(),
+            chapter: v[1].to_string(),
+        })
+    })
+}
+
+/// The name of a book without a title, from its file name.
+pub fn book_name(path: &str) -> String {
+    Path::new(path)
+        .file_stem()
+        .map_or_else(|| path.to_string(), |s| s.to_string_lossy().into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+    use zip::write::{FileOptions, ZipWriter};
+
+    fn epub(files: &[(&str, &str)]) -> Cursor<Vec<u8>> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, text) in files {
+            zip.start_file(*name, FileOptions::default()).unwrap();
+            zip.write_all(text.as_bytes()).unwrap();
+        }
+        let mut cursor = zip.finish().unwrap();
+        cursor.set_position(0);
+        cursor
+    }
+
+    #[test]
+    fn test_resolve() {
+        assert_eq!(
+            resolve("OEBPS", "Text/ch%201.xhtml#p1"),
+            "OEBPS/Text/ch 1.xhtml"
+        );
+        assert_eq!(
+            resolve("OEBPS/Text", "../Images/a.png"),
+            "OEBPS/Images/a.png"
+        );
+        assert_eq!(resolve("", "a.xhtml"), "a.xhtml");
+    }
+
+    #[test]
+    fn test_parse_xhtml_entities() {
+        let (title, sentences) = parse_xhtml(
+            "<body><h1>&lt;A&amp;#x41;&gt;</h1><p>&#x41;&amp;B&#12288;です。</p></body>",
+            &mut ReadingHints::new(),
+        );
+        assert_eq!(title.as_deref(), Some("<A&#x41;>"));
+        assert_eq!(sentences, vec!["<A&#x41;>", "A&B\u{3000}です。"]);
+    }
+
+    #[test]
+    fn test_read_epub() {
+        let book = read_epub_from(
+            epub(&[
+                (
+                    "META-INF/container.xml",
+                    r#"<?xml version="1.0"?><container xmlns="urn:oasis:names:tc:opendocument:xmlns:container"><rootfiles><rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/></rootfiles></container>"#,
+                ),
+                (
+                    "OEBPS/content.opf",
+                    r#"<package xmlns="http://www.idpf.org/2007/opf" xmlns:dc="http://purl.org/dc/elements/1.1/"><metadata><dc:title>鬼の話</dc:title></metadata><manifest><item id="c2" href="Text/two.xhtml" media-type="application/xhtml+xml"/><item id="c1" href="Text/one.xhtml" media-type="application/xhtml+xml"/><item id="c3" href="Text/lost.xhtml" media-type="application/xhtml+xml"/></manifest><spine><itemref idref="c1"/><itemref idref="c3"/><itemref idref="c2"/></spine></package>"#,
+                ),
+                (
+                    "OEBPS/Text/one.xhtml",
+                    "<html><head><title>one</title></head><body>\n<h1>第一章</h1>\n<p><ruby>竈門<rt>かまど</rt></ruby>は\n山に住む。&#12288;鬼を見た。</p></body></html>",
+                ),
+                (
+                    "OEBPS/Text/two.xhtml",
+                    "<html><head><title>第二章</title></head><body><p>友達に会った。</p></body></html>",
+                ),
+            ]),
+            "book.epub",
+        )
+        .unwrap();
+        assert_eq!(book.title.as_deref(), Some("鬼の話"));
+        assert_eq!(book.chapters[0].title.as_deref(), Some("第一章"));
+        assert_eq!(book.chapters[1].title.as_deref(), Some("第二章"));
+        assert_eq!(book.chapters.len(), 2);
+        assert_eq!(
+            book.sentences,
+            vec![
+                (0, "第一章".to_string()),
+                (0, "竈門は山に住む。".to_string()),
+                (0, "鬼を見た。".to_string()),
+                (1, "友達に会った。".to_string()),
+            ]
+        );
+        assert_eq!(book.hints["竈門"], "かまど");
+
+        let words = vec!["会う".to_string(), "鬼".to_string()];
+        let (pairing, locations) = pair_book(&book, "book", &words);
+        assert_eq!(pairing.sentences, vec!["友達に会った。", "鬼を見た。"]);
+        assert_eq!(locations[&0].chapter, "第二章");
+        assert_eq!(locations[&1].chapter, "第一章");
+        assert_eq!(locations[&1].book, "鬼の話");
+    }
+}
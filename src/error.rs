@@ -0,0 +1,125 @@
+use std::io;
+use std::string::FromUtf8Error;
+
+/// Everything that can go wrong between the Goldendict export and the csv.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("could not access {path}")]
+    Io {
+        path: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("could not parse the xml in {path}")]
+    Xml {
+        path: String,
+        #[source]
+        source: quick_xml::DeError,
+    },
+    #[error("could not parse the json in {path}")]
+    Json {
+        path: String,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("could not read or write the archive {path}")]
+    Zip {
+        path: String,
+        #[source]
+        source: zip::result::ZipError,
+    },
+    #[error("{path}:{line}: {reason}: {text:?}")]
+    Line {
+        path: String,
+        line: usize,
+        text: String,
+        reason: String,
+    },
+    #[error("could not query the database {path}")]
+    Database {
+        path: String,
+        #[source]
+        source: rusqlite::Error,
+    },
+    #[error("could not write the csv")]
+    Csv(#[from] csv::Error),
+    #[error("the csv is not valid utf8")]
+    Utf8(#[from] FromUtf8Error),
+    #[error("could not reach AnkiConnect at {url}")]
+    Http {
+        url: String,
+        #[source]
+        source: Box<ureq::Error>,
+    },
+    #[error("AnkiConnect refused {action}: {error}")]
+    AnkiConnect { action: String, error: String },
+    #[error("{0} is not a field of the mining cards")]
+    UnknownField(String),
+    #[cfg(feature = "builtin-tokenizer")]
+    #[error("the builtin tokenizer failed")]
+    Tokenizer(#[from] lindera_core::error::LinderaError),
+    #[error("{program} exited with {status}")]
+    Command { program: String, status: String },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// Wraps an io error with the path it happened on, for `map_err`.
+    pub fn io(path: &str) -> impl FnOnce(io::Error) -> Error + '_ {
+        move |source| Error::Io {
+            path: path.to_string(),
+            source,
+        }
+    }
+
+    /// Wraps an xml error with the file it happened in, for `map_err`.
+    pub fn xml<E: Into<quick_xml::DeError>>(path: &str) -> impl FnOnce(E) -> Error + '_ {
+        move |source| Error::Xml {
+            path: path.to_string(),
+            source: source.into(),
+        }
+    }
+
+    /// Wraps a json error with the file it happened in, for `map_err`.
+    pub fn json(path: &str) -> impl FnOnce(serde_json::Error) -> Error + '_ {
+        move |source| Error::Json {
+            path: path.to_string(),
+            source,
+        }
+    }
+
+    /// Wraps a zip error with the archive it happened in, for `map_err`.
+    pub fn zip(path: &str) -> impl FnOnce(zip::result::ZipError) -> Error + '_ {
+        move |source| Error::Zip {
+            path: path.to_string(),
+            source,
+        }
+    }
+
+    /// Wraps an http error with the url requested, for `map_err`.
+    pub fn http(url: &str) -> impl FnOnce(ureq::Error) -> Error + '_ {
+        move |source| Error::Http {
+            url: url.to_string(),
+            source: Box::new(source),
+        }
+    }
+
+    /// Wraps a database error with the database path, for `map_err`.
+    pub fn database(path: &str) -> impl FnOnce(rusqlite::Error) -> Error + '_ {
+        move |source| Error::Database {
+            path: path.to_string(),
+            source,
+        }
+    }
+
+    /// A single bad line of an input file.
+    pub fn line(path: &str, line: usize, text: &str, reason: &str) -> Error {
+        Error::Line {
+            path: path.to_string(),
+            line,
+            text: text.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+}
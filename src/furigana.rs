@@ -0,0 +1,230 @@
+use difference::{Changeset, Difference};
+use regex::Regex;
+
+struct StemOkurigana {
+    stem: String,
+    conjugation_part: String,
+    okurigana: String,
+}
+
+fn get_stem_okurigana(original: &str, dictionary_form: &str) -> StemOkurigana {
+    if original == dictionary_form {
+        StemOkurigana {
+            stem: original.to_string(),
+            conjugation_part: "".to_string(),
+            okurigana: "".to_string(),
+        }
+    } else {
+        let changeset = Changeset::new(original, dictionary_form, "");
+
+        let mut stem = "".to_string();
+        let mut conjugation_part = "".to_string();
+        let mut okurigana = "".to_string();
+
+        for diff in changeset.diffs {
+            if let Difference::Same(text) = diff {
+                stem = text;
+            } else if let Difference::Rem(text) = diff {
+                conjugation_part = text;
+            } else if let Difference::Add(text) = diff {
+                okurigana = text;
+            }
+        }
+
+        StemOkurigana {
+            stem,
+            conjugation_part,
+            okurigana,
+        }
+    }
+}
+
+/// Dictionary form of a token with its readings.
+#[derive(Debug)]
+pub struct AnkiReading {
+    // NOTE: adds "る" to verb, "だ" and whatever undesired stuff are removed
+    pub word: String,
+    pub furigana: String,
+    pub kana: String,
+}
+
+/// Turns a conjugated token back into its dictionary form and reads it.
+///
+/// `original` is the surface form, `yomi_original` its reading in hiragana,
+/// `dictionary_form` the analyzer's lemma and `word_pos` its part of speech.
+pub fn get_reading_stem(
+    original: &str,
+    yomi_original: &str,
+    dictionary_form: &str,
+    word_pos: &str,
+) -> AnkiReading {
+    if original == dictionary_form {
+        return AnkiReading {
+            word: original.to_string(),
+            furigana: get_furigana_reading(original, yomi_original, false),
+            kana: yomi_original.to_string(),
+        };
+    }
+
+    let tmp = get_stem_okurigana(original, dictionary_form);
+    let mut stem = tmp.stem;
+
+    let conjugation_part = tmp.conjugation_part;
+    let mut okurigana = tmp.okurigana;
+
+    // orig: 痛快, dict: 痛快だ
+    if conjugation_part.is_empty() && !okurigana.is_empty() {
+        if okurigana == "だ" && word_pos == "形容詞" {
+            okurigana = "".to_string();
+        }
+        AnkiReading {
+            word: format!("{}{}", stem, okurigana),
+            furigana: format!(
+                "{}{}",
+                get_furigana_reading(original, yomi_original, false),
+                okurigana
+            ),
+            kana: format!("{}{}", yomi_original, okurigana),
+        }
+    } else if !conjugation_part.is_empty() && !okurigana.is_empty() {
+        // orig: 尋ねて, dict: 尋ねる
+        let yomi_rev = yomi_original.chars().rev().collect::<String>();
+        let conjugation_part_rev = conjugation_part.chars().rev().collect::<String>();
+
+        let re = Regex::new(&regex::escape(&conjugation_part_rev))
+            .expect("escaped pattern is always valid");
+        let kana_stem = re.replace(&yomi_rev, "").chars().rev().collect::<String>();
+
+        if okurigana == "だ" && word_pos == "形容詞" {
+            okurigana = "".to_string();
+        }
+
+        AnkiReading {
+            word: format!("{}{}", stem, okurigana),
+            furigana: format!(
+                "{}{}",
+                get_furigana_reading(&stem, &kana_stem, false),
+                okurigana
+            ),
+            kana: format!("{}{}", kana_stem, okurigana),
+        }
+    } else {
+        //orig: 空白だった, yomi: くうはくだった, dict: 空白だ, pos: 形容詞
+        //stem: 空白だ, conjugation_part: った, okurigana:
+        let yomi_rev = yomi_original.chars().rev().collect::<String>();
+        let orig_rev = original.chars().rev().collect::<String>();
+
+        let changeset = Changeset::new(&yomi_rev, &orig_rev, "");
+        let mut kana_stem = "".to_string();
+        for diff in changeset.diffs {
+            if let Difference::Rem(text) = diff {
+                kana_stem = text.chars().rev().collect::<String>();
+            }
+        }
+
+        let mut dict_santinize = stem.clone();
+        let try_pop = stem.pop();
+
+        if try_pop == Some('だ') && word_pos == "形容詞" {
+            dict_santinize.pop();
+        }
+
+        AnkiReading {
+            word: format!("{}{}", dict_santinize, okurigana),
+            furigana: format!(
+                "{}{}",
+                get_furigana_reading(&dict_santinize, &kana_stem, false),
+                okurigana
+            ),
+            kana: format!("{}{}", kana_stem, okurigana),
+        }
+    }
+}
+
+/// Reads the canonical form an analyzer gave for a token, which needs none of
+/// the okurigana guessing of `get_reading_stem`.
+pub fn get_canonical_reading(kanji: &str, reading: &str, word_pos: &str) -> AnkiReading {
+    let mut word = kanji.to_string();
+    let mut kana = reading.to_string();
+    // 痛快だ/つうかいだ
+    if word_pos == "形容詞" && word.ends_with('だ') && kana.ends_with('だ') {
+        word.pop();
+        kana.pop();
+    }
+
+    AnkiReading {
+        furigana: get_furigana_reading(&word, &kana, false),
+        word,
+        kana,
+    }
+}
+
+/// Annotates the kanji of `kanji` with the matching parts of `yomi`, either as
+/// `<ruby>` markup or as `漢字[かんじ]` when `plain_text` is set.
+pub fn get_furigana_reading(kanji: &str, yomi: &str, plain_text: bool) -> String {
+    let mut text = String::new();
+
+    let changeset = Changeset::new(kanji, yomi, "");
+    for (i, _x) in changeset.diffs.iter().enumerate() {
+        if let Difference::Rem(kanji) = &changeset.diffs[i] {
+            if let Some(Difference::Add(furigana)) = changeset.diffs.get(i + 1) {
+                if plain_text {
+                    // THIS DOES NOT WORK WITH ANKI
+                    text += format!("{}[{}]", kanji, furigana).as_str();
+                } else {
+                    text += format!("<ruby><rb>{}<rt>{}</ruby>", kanji, furigana).as_str();
+                }
+            } else if let Some(Difference::Same(furigana)) = changeset.diffs.get(i + 1) {
+                if plain_text {
+                    // THIS DOES NOT WORK WITH ANKI
+                    text += format!("{}[{}]", kanji, furigana).as_str();
+                } else {
+                    text += format!("<ruby><rb>{}<rt>{}</ruby>", kanji, furigana).as_str();
+                }
+            }
+        } else if let Difference::Same(same) = &changeset.diffs[i] {
+            text += same;
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_furigana_reading() {
+        assert_eq!(
+            get_furigana_reading("友達", "ともだち", false),
+            "<ruby><rb>友達<rt>ともだち</ruby>"
+        );
+        assert_eq!(
+            get_furigana_reading("尋ねる", "たずねる", true),
+            "尋[たず]ねる"
+        );
+    }
+
+    #[test]
+    fn test_reading_stem_verb() {
+        let p = get_reading_stem("尋ねて", "たずねて", "尋ねる", "動詞");
+        assert_eq!(p.word, "尋ねる");
+        assert_eq!(p.kana, "たずねる");
+        assert_eq!(p.furigana, "<ruby><rb>尋<rt>たず</ruby>ねる");
+    }
+
+    #[test]
+    fn test_canonical_reading() {
+        let p = get_canonical_reading("痛快だ", "つうかいだ", "形容詞");
+        assert_eq!(p.word, "痛快");
+        assert_eq!(p.kana, "つうかい");
+        assert_eq!(p.furigana, "<ruby><rb>痛快<rt>つうかい</ruby>");
+    }
+
+    #[test]
+    fn test_reading_stem_unchanged() {
+        let p = get_reading_stem("友達", "ともだち", "友達", "名詞");
+        assert_eq!(p.word, "友達");
+        assert_eq!(p.kana, "ともだち");
+    }
+}
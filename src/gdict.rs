@@ -0,0 +1,239 @@
+use directories::BaseDirs;
+use log::warn;
+use quick_xml::de::from_str;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use serde::Deserialize;
+use std::convert::TryFrom;
+use std::fs;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+
+use crate::error::{Error, Result};
+
+/// A looked up word paired with the sentence it was found in.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct Vocab {
+    #[serde(alias = "word")]
+    pub kanji: String,
+    pub sentence: String,
+}
+
+/// A list of explicit pairs, e.g.
+/// `<pairs><vocab><kanji>道</kanji><sentence>道を尋ねる</sentence></vocab></pairs>`.
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct VocabPairs {
+    #[serde(default)]
+    pub vocab: Vec<Vocab>,
+}
+
+/// Reads a file of explicit word and sentence pairs.
+pub fn read_vocab_pairs(path: &str) -> Result<Vec<Vocab>> {
+    let s = fs::read_to_string(path).map_err(Error::io(path))?;
+    let pairs: VocabPairs = from_str(&s).map_err(Error::xml(path))?;
+    Ok(pairs.vocab)
+}
+
+/// The `<headword>`s of a Goldendict export in the order they appear, read
+/// one at a time so that memory stays bounded however large the export is.
+pub struct GdictHeadwords<R: BufRead> {
+    reader: Reader<R>,
+    buf: Vec<u8>,
+    path: String,
+    done: bool,
+}
+
+impl GdictHeadwords<BufReader<File>> {
+    /// Opens the export at `path`.
+    pub fn open(path: &str) -> Result<Self> {
+        let file = File::open(path).map_err(Error::io(path))?;
+        Ok(GdictHeadwords::from_reader(BufReader::new(file), path))
+    }
+}
+
+impl<R: BufRead> GdictHeadwords<R> {
+    /// Reads an export from `reader`, `path` naming it in errors.
+    pub fn from_reader(reader: R, path: &str) -> Self {
+        let mut reader = Reader::from_reader(reader);
+        reader
+            .expand_empty_elements(true)
+            .check_end_names(true)
+            .trim_text(true);
+        GdictHeadwords {
+            reader,
+            buf: Vec::new(),
+            path: path.to_string(),
+            done: false,
+        }
+    }
+
+    fn next_headword(&mut self) -> Result<Option<String>> {
+        let mut headword: Option<String> = None;
+        loop {
+            self.buf.clear();
+            match self
+                .reader
+                .read_event(&mut self.buf)
+                .map_err(Error::xml(&self.path))?
+            {
+                Event::Start(e) if e.local_name() == b"headword" => headword = Some(String::new()),
+                Event::Text(e) | Event::CData(e) => {
+                    if let Some(headword) = headword.as_mut() {
+                        *headword += &e
+                            .unescape_and_decode(&self.reader)
+                            .map_err(Error::xml(&self.path))?;
+                    }
+                }
+                Event::End(e) if e.local_name() == b"headword" && headword.is_some() => {
+                    return Ok(headword)
+                }
+                Event::Eof => return Ok(None),
+                _ => {}
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for GdictHeadwords<R> {
+    type Item = Result<String>;
+
+    fn next(&mut self) -> Option<Result<String>> {
+        if self.done {
+            return None;
+        }
+        let headword = self.next_headword();
+        self.done = !matches!(headword, Ok(Some(_)));
+        headword.transpose()
+    }
+}
+
+/// One line of Goldendict's own `history` file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryEntry {
+    pub timestamp: Option<u64>, // unix seconds, only written by some builds
+    pub group_id: u32,
+    pub headword: String,
+}
+
+/// Where Goldendict keeps its `history`, if it exists: `~/.goldendict` for
+/// older builds, the config directory (`~/.config/goldendict`,
+/// `%APPDATA%\GoldenDict`) for newer ones.
+pub fn default_history_path() -> Option<PathBuf> {
+    let dirs = BaseDirs::new()?;
+    [
+        dirs.config_dir().join("goldendict").join("history"),
+        dirs.config_dir().join("GoldenDict").join("history"),
+        dirs.home_dir().join(".goldendict").join("history"),
+    ]
+    .iter()
+    .find(|p| p.is_file())
+    .cloned()
+}
+
+/// Parses one line of the `history` file, `group headword` or
+/// `timestamp group headword`. Only a first field of at least 10 digits is
+/// taken for a timestamp, so that `3 100 円` is the headword 100 円.
+pub fn parse_history_line(line: &str) -> Option<HistoryEntry> {
+    let (first, rest) = line.trim().split_once(' ')?;
+    let is_timestamp = first.len() >= 10;
+    let first: u64 = first.parse().ok()?;
+
+    let entry = match rest.split_once(' ') {
+        Some((group_id, headword)) if is_timestamp && group_id.parse::<u32>().is_ok() => {
+            HistoryEntry {
+                timestamp: Some(first),
+                group_id: group_id.parse().ok()?,
+                headword: headword.trim().to_string(),
+            }
+        }
+        _ => HistoryEntry {
+            timestamp: None,
+            group_id: u32::try_from(first).ok()?,
+            headword: rest.trim().to_string(),
+        },
+    };
+    (!entry.headword.is_empty()).then_some(entry)
+}
+
+/// Reads Goldendict's `history` file in the order Goldendict lists it, the
+/// same as its xml export. Malformed lines are reported and skipped.
+pub fn read_gdict_history(path: &str) -> Result<Vec<HistoryEntry>> {
+    let file = File::open(path).map_err(Error::io(path))?;
+    read_gdict_history_from(BufReader::new(file), path)
+}
+
+/// Like `read_gdict_history`, from `reader`, `path` naming it in errors.
+pub fn read_gdict_history_from<R: BufRead>(reader: R, path: &str) -> Result<Vec<HistoryEntry>> {
+    let mut entries = Vec::new();
+    for (line, x) in reader.lines().enumerate() {
+        let x = x.map_err(Error::io(path))?;
+        let x = x.as_str();
+        if x.trim().is_empty() {
+            continue;
+        }
+        match parse_history_line(x) {
+            Some(entry) => entries.push(entry),
+            None => warn!(
+                "skipping {}",
+                Error::line(path, line + 1, x, "not a history line")
+            ),
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_headwords() {
+        let xml = "<?xml version=\"1.0\"?><history>\n  <headword>彼は駅で友達に道を尋ねていた。</headword>\n  <headword>A&amp;B</headword><headword/>\n  <headword>友達</headword></history>";
+        let headwords: Vec<String> = GdictHeadwords::from_reader(xml.as_bytes(), "history.xml")
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(
+            headwords,
+            vec!["彼は駅で友達に道を尋ねていた。", "A&B", "", "友達"]
+        );
+    }
+
+    #[test]
+    fn test_headwords_error() {
+        let mut headwords =
+            GdictHeadwords::from_reader("<history><headword>友達</history>".as_bytes(), "h.xml");
+        assert!(headwords.next().unwrap().is_err());
+        assert!(headwords.next().is_none());
+    }
+
+    #[test]
+    fn test_parse_history_line() {
+        assert_eq!(
+            parse_history_line("3 尋ねる"),
+            Some(HistoryEntry {
+                timestamp: None,
+                group_id: 3,
+                headword: "尋ねる".to_string(),
+            })
+        );
+        assert_eq!(
+            parse_history_line("1666000000 3 彼は駅で 友達に会った"),
+            Some(HistoryEntry {
+                timestamp: Some(1666000000),
+                group_id: 3,
+                headword: "彼は駅で 友達に会った".to_string(),
+            })
+        );
+        assert_eq!(
+            parse_history_line("3 100 円"),
+            Some(HistoryEntry {
+                timestamp: None,
+                group_id: 3,
+                headword: "100 円".to_string(),
+            })
+        );
+        assert_eq!(parse_history_line("尋ねる"), None);
+        assert_eq!(parse_history_line("3 "), None);
+    }
+}
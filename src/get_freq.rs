@@ -1,86 +1,79 @@
-use rusqlite::{params, Connection};
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::error::{Error, Result};
 
 //let freq = get_freq_narou("database/freq.db", &i.word.dictionary_form, &i.word.reading_kana);
-pub fn get_freq_narou(file_path: &str, word: &str, reading: &str) -> Option<u32> {
-    let conn = Connection::open(file_path).expect("could not open database file");
-    let query_result: rusqlite::Result<u32> = conn.query_row_and_then(
-        "SELECT freq FROM narou WHERE word=(?1) AND reading=(?2)",
-        params![word, reading],
-        |row| row.get(0),
-    );
+/// Looks up the narou frequency rank of `word` read as `reading`, retrying
+/// without the last character of both (e.g. the る of a verb).
+pub fn get_freq_narou(file_path: &str, word: &str, reading: &str) -> Result<Option<u32>> {
+    let conn = Connection::open(file_path).map_err(Error::database(file_path))?;
+    let query_result: Option<u32> = conn
+        .query_row(
+            "SELECT freq FROM narou WHERE word=(?1) AND reading=(?2)",
+            params![word, reading],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(Error::database(file_path))?;
     match query_result {
-        Ok(freq) => {
-            return Some(freq);
-        }
-        Err(_e) => {
+        Some(freq) => Ok(Some(freq)),
+        None => {
             let mut tmp_word = word.to_string();
             tmp_word.pop();
             let mut tmp_reading = reading.to_string();
             tmp_reading.pop();
-            let query2_result: rusqlite::Result<u32> = conn.query_row_and_then(
+            conn.query_row(
                 "SELECT freq FROM narou WHERE word=(?1) AND reading=(?2)",
                 params![tmp_word, tmp_reading],
                 |row| row.get(0),
-            );
-            match query2_result {
-                Ok(freq) => {
-                    return Some(freq);
-                }
-                Err(_e) => {
-                    return None;
-                }
-            }
+            )
+            .optional()
+            .map_err(Error::database(file_path))
         }
     }
 }
 
 //let freq = get_freq_2016("database/freq.db", &i.word.dictionary_form, "netflix");
 //let freq = get_freq_2016("database/freq.db", &i.word.dictionary_form, "anime_jdrama");
-pub fn get_freq_anime_jdrama(file_path: &str, word: &str, table_name: &str) -> Option<u32> {
-    let conn = Connection::open(file_path).expect("could not open database file");
-    let query_result: rusqlite::Result<u32> = conn.query_row_and_then(
-        format!("SELECT freq FROM {} WHERE word=(?1)", table_name).as_str(),
-        params![word],
-        |row| row.get(0),
-    );
-    match query_result {
-        Ok(freq) => {
-            return Some(freq);
-        }
-        Err(_e) => {
-            return None;
-        }
-    }
+/// Looks up the rank of `word` in a word-only table, `netflix` or `anime_jdrama`.
+pub fn get_freq_anime_jdrama(file_path: &str, word: &str, table_name: &str) -> Result<Option<u32>> {
+    let conn = Connection::open(file_path).map_err(Error::database(file_path))?;
+    let query_result: Option<u32> = conn
+        .query_row(
+            format!("SELECT freq FROM {} WHERE word=(?1)", table_name).as_str(),
+            params![word],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(Error::database(file_path))?;
+    Ok(query_result)
 }
 
 //let freq = get_freq_2016("database/freq.db", &i.word.dictionary_form);
-pub fn get_freq_2016(file_path: &str, word: &str) -> Option<u32> {
-    let conn = Connection::open(file_path).expect("could not open database file");
-    let query_result: rusqlite::Result<u32> = conn.query_row_and_then(
-        "SELECT freq FROM freq2016 WHERE word=(?1)",
-        params![word],
-        |row| row.get(0),
-    );
+/// Looks up the 2016 Japanese frequency rank of `word`, retrying without its
+/// last character.
+pub fn get_freq_2016(file_path: &str, word: &str) -> Result<Option<u32>> {
+    let conn = Connection::open(file_path).map_err(Error::database(file_path))?;
+    let query_result: Option<u32> = conn
+        .query_row(
+            "SELECT freq FROM freq2016 WHERE word=(?1)",
+            params![word],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(Error::database(file_path))?;
     match query_result {
-        Ok(freq) => {
-            return Some(freq);
-        }
-        Err(_e) => {
+        Some(freq) => Ok(Some(freq)),
+        None => {
             let mut tmp_word = word.to_string();
             tmp_word.pop();
-            let query2_result: rusqlite::Result<u32> = conn.query_row_and_then(
+            conn.query_row(
                 "SELECT freq FROM freq2016 WHERE word=(?1)",
                 params![tmp_word],
                 |row| row.get(0),
-            );
-            match query2_result {
-                Ok(freq) => {
-                    return Some(freq);
-                }
-                Err(_e) => {
-                    return None;
-                }
-            }
+            )
+            .optional()
+            .map_err(Error::database(file_path))
         }
     }
 }
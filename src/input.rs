@@ -0,0 +1,349 @@
+use csv::ReaderBuilder;
+use log::warn;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+use crate::error::{Error, Result};
+use crate::gdict::{read_gdict_history_from, GdictHeadwords, HistoryEntry, Vocab};
+use crate::kindle::{kindle_vocabs, read_kindle_lookups, KindleLookup};
+use crate::pairing::{pair_all, Pairing, PairingRules};
+
+/// The lookups `extract` can read.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputFormat {
+    /// the xml history exported by Goldendict
+    Gdict,
+    /// Goldendict's own `history` file
+    History,
+    /// one headword per line, paired like the Goldendict history
+    Text,
+    /// `word<TAB>sentence` lines
+    Tsv,
+    /// `word,sentence` rows
+    Csv,
+    /// one `{"kanji": .., "sentence": ..}` object per line, `word` also works
+    Jsonl,
+    /// the `vocab.db` of the Kindle Vocabulary Builder
+    Kindle,
+}
+
+impl InputFormat {
+    /// Guesses the format from the extension of `path`.
+    pub fn from_path(path: &str) -> Option<InputFormat> {
+        let path = Path::new(path);
+        match path.file_name()?.to_str()? {
+            "history" => return Some(InputFormat::History),
+            "vocab.db" => return Some(InputFormat::Kindle),
+            _ => {}
+        }
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "xml" => Some(InputFormat::Gdict),
+            "txt" => Some(InputFormat::Text),
+            "tsv" | "tab" => Some(InputFormat::Tsv),
+            "csv" => Some(InputFormat::Csv),
+            "jsonl" | "ndjson" => Some(InputFormat::Jsonl),
+            _ => None,
+        }
+    }
+}
+
+/// Opens `path` for reading, `-` being stdin.
+pub fn open_input(path: &str) -> Result<Box<dyn BufRead>> {
+    if path == "-" {
+        return Ok(Box::new(BufReader::new(io::stdin())));
+    }
+    let file = File::open(path).map_err(Error::io(path))?;
+    Ok(Box::new(BufReader::new(file)))
+}
+
+/// Reads one headword per line, skipping blank lines.
+pub fn read_text_headwords<'a, R: BufRead + 'a>(
+    reader: R,
+    path: &'a str,
+) -> impl Iterator<Item = Result<String>> + 'a {
+    reader
+        .lines()
+        .map(move |line| line.map_err(Error::io(path)))
+        .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+}
+
+/// Reads `word,sentence` rows separated by `delimiter`, skipping a
+/// `word,sentence` or `kanji,sentence` header and reporting malformed rows.
+pub fn read_vocab_table<R: BufRead>(reader: R, path: &str, delimiter: u8) -> Result<Vec<Vocab>> {
+    let mut rdr = ReaderBuilder::new()
+        .delimiter(delimiter)
+        .has_headers(false)
+        .flexible(true)
+        // a tab separated sentence may well contain a lone "
+        .quoting(delimiter != b'\t')
+        .from_reader(reader);
+
+    let mut vocabs = Vec::new();
+    for (line, record) in rdr.records().enumerate() {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) if !matches!(e.kind(), csv::ErrorKind::Io(_)) => {
+                let line = e.position().map_or(line + 1, |p| p.line() as usize);
+                warn!("skipping {}", Error::line(path, line, "", &e.to_string()));
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let text = record.iter().collect::<Vec<_>>().join(" ");
+        match (record.get(0).map(str::trim), record.get(1).map(str::trim)) {
+            (Some("word" | "kanji"), Some("sentence")) if line == 0 => {}
+            (Some(kanji), Some(sentence)) if !kanji.is_empty() && !sentence.is_empty() => vocabs
+                .push(Vocab {
+                    kanji: kanji.to_string(),
+                    sentence: sentence.to_string(),
+                }),
+            _ if text.trim().is_empty() => {}
+            _ => warn!(
+                "skipping {}",
+                Error::line(path, line + 1, &text, "not a word and sentence row")
+            ),
+        }
+    }
+    Ok(vocabs)
+}
+
+/// Reads one json object per line, reporting malformed lines.
+pub fn read_vocab_jsonl<R: BufRead>(reader: R, path: &str) -> Result<Vec<Vocab>> {
+    let mut vocabs = Vec::new();
+    for (line, x) in reader.lines().enumerate() {
+        let x = x.map_err(Error::io(path))?;
+        if x.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&x) {
+            Ok(vocab) => vocabs.push(vocab),
+            Err(e) => warn!(
+                "skipping {}",
+                Error::line(path, line + 1, &x, &e.to_string())
+            ),
+        }
+    }
+    Ok(vocabs)
+}
+
+/// Reads Goldendict's history, leaving out the lookups made before `since`
+/// (unix seconds). Entries without a time are kept, there is no telling.
+fn read_history(path: &str, since: Option<u64>) -> Result<Vec<HistoryEntry>> {
+    let mut entries = read_gdict_history_from(open_input(path)?, path)?;
+    if entries.iter().all(|entry| entry.timestamp.is_none()) {
+        warn_untimed(path, since);
+    }
+    if let Some(since) = since {
+        entries.retain(|entry| entry.timestamp.is_none_or(|t| t >= since));
+    }
+    Ok(entries)
+}
+
+/// Reads the Kindle lookups, leaving out those made before `since`.
+fn read_kindle(path: &str, since: Option<u64>) -> Result<Vec<KindleLookup>> {
+    let mut lookups = read_kindle_lookups(path)?;
+    if let Some(since) = since {
+        // the Kindle counts in milliseconds
+        lookups.retain(|lookup| lookup.timestamp / 1000 >= since as i64);
+    }
+    Ok(lookups)
+}
+
+fn warn_untimed(path: &str, since: Option<u64>) {
+    if since.is_some() {
+        warn!("{} has no lookup times, reading all of it", path);
+    }
+}
+
+/// Reads just the looked up words at `path` (`-` for stdin): every headword, or
+/// the word of every pair. Lookups made before `since` (unix seconds) are left
+/// out when the input records when they were made.
+pub fn read_headwords(path: &str, format: InputFormat, since: Option<u64>) -> Result<Vec<String>> {
+    Ok(read_timed_headwords(path, format, since)?
+        .into_iter()
+        .map(|(headword, _)| headword)
+        .collect())
+}
+
+/// Like `read_headwords`, with the unix time of each lookup when the input has
+/// it, which only the `history` of some Goldendict builds does.
+pub fn read_timed_headwords(
+    path: &str,
+    format: InputFormat,
+    since: Option<u64>,
+) -> Result<Vec<(String, Option<u64>)>> {
+    let untimed = |headwords: Vec<String>| {
+        warn_untimed(path, since);
+        headwords
+            .into_iter()
+            .map(|headword| (headword, None))
+            .collect()
+    };
+    Ok(match format {
+        InputFormat::History => read_history(path, since)?
+            .into_iter()
+            .map(|entry| (entry.headword, entry.timestamp))
+            .collect(),
+        InputFormat::Kindle => read_kindle(path, since)?
+            .iter()
+            .map(|lookup| (lookup.headword().to_string(), None))
+            .collect(),
+        InputFormat::Gdict => {
+            untimed(GdictHeadwords::from_reader(open_input(path)?, path).collect::<Result<_>>()?)
+        }
+        InputFormat::Text => {
+            untimed(read_text_headwords(open_input(path)?, path).collect::<Result<_>>()?)
+        }
+        InputFormat::Tsv => untimed(vocab_words(read_vocab_table(
+            open_input(path)?,
+            path,
+            b'\t',
+        )?)),
+        InputFormat::Csv => untimed(vocab_words(read_vocab_table(
+            open_input(path)?,
+            path,
+            b',',
+        )?)),
+        InputFormat::Jsonl => untimed(vocab_words(read_vocab_jsonl(open_input(path)?, path)?)),
+    })
+}
+
+fn vocab_words(vocabs: Vec<Vocab>) -> Vec<String> {
+    vocabs.into_iter().map(|vocab| vocab.kanji).collect()
+}
+
+/// Reads the lookups at `path` (`-` for stdin, but for `vocab.db`) in `format`
+/// made since `since`, see `read_headwords`, and pairs them into sentences and
+/// words, adding the `explicit` pairs.
+pub fn parse_input(
+    path: &str,
+    format: InputFormat,
+    since: Option<u64>,
+    rules: &PairingRules,
+    explicit: &[Vocab],
+) -> Result<Pairing> {
+    Ok(match format {
+        InputFormat::History => {
+            let headwords = read_history(path, since)?
+                .into_iter()
+                .map(|entry| Ok(entry.headword));
+            pair_all(headwords, explicit, rules)?
+        }
+        InputFormat::Gdict | InputFormat::Text => {
+            warn_untimed(path, since);
+            let reader = open_input(path)?;
+            if format == InputFormat::Gdict {
+                pair_all(GdictHeadwords::from_reader(reader, path), explicit, rules)?
+            } else {
+                pair_all(read_text_headwords(reader, path), explicit, rules)?
+            }
+        }
+        InputFormat::Tsv | InputFormat::Csv | InputFormat::Jsonl | InputFormat::Kindle => {
+            let mut vocabs = match format {
+                InputFormat::Tsv => read_vocab_table(open_input(path)?, path, b'\t')?,
+                InputFormat::Csv => read_vocab_table(open_input(path)?, path, b',')?,
+                InputFormat::Jsonl => read_vocab_jsonl(open_input(path)?, path)?,
+                _ => kindle_vocabs(&read_kindle(path, since)?),
+            };
+            if format != InputFormat::Kindle {
+                warn_untimed(path, since);
+            }
+            vocabs.extend_from_slice(explicit);
+            pair_all(Vec::new(), &vocabs, rules)?
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vocab(kanji: &str, sentence: &str) -> Vocab {
+        Vocab {
+            kanji: kanji.to_string(),
+            sentence: sentence.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_from_path() {
+        assert_eq!(
+            InputFormat::from_path("a/hist.XML"),
+            Some(InputFormat::Gdict)
+        );
+        assert_eq!(
+            InputFormat::from_path("/home/a/.goldendict/history"),
+            Some(InputFormat::History)
+        );
+        assert_eq!(InputFormat::from_path("words.tsv"), Some(InputFormat::Tsv));
+        assert_eq!(
+            InputFormat::from_path("/media/Kindle/system/vocabulary/vocab.db"),
+            Some(InputFormat::Kindle)
+        );
+        assert_eq!(InputFormat::from_path("-"), None);
+    }
+
+    #[test]
+    fn test_read_tsv() {
+        let tsv = "word\tsentence\n友達\t\"友達\"に会う\textra\n\n道\n";
+        let vocabs = read_vocab_table(tsv.as_bytes(), "a.tsv", b'\t').unwrap();
+        assert_eq!(vocabs, vec![vocab("友達", "\"友達\"に会う")]);
+    }
+
+    #[test]
+    fn test_read_csv() {
+        let csv = "友達,\"友達に会う、そして話す\"\n";
+        let vocabs = read_vocab_table(csv.as_bytes(), "a.csv", b',').unwrap();
+        assert_eq!(vocabs, vec![vocab("友達", "友達に会う、そして話す")]);
+    }
+
+    #[test]
+    fn test_read_csv_bad_row() {
+        let csv =
+            b"\xff\xfe,broken\n\xe9\x81\x93,\xe9\x81\x93\xe3\x82\x92\xe6\xad\xa9\xe3\x81\x8f\n";
+        let vocabs = read_vocab_table(&csv[..], "a.csv", b',').unwrap();
+        assert_eq!(vocabs, vec![vocab("道", "道を歩く")]);
+    }
+
+    #[test]
+    fn test_read_jsonl() {
+        let jsonl = "{\"word\": \"友達\", \"sentence\": \"友達に会う\"}\nnot json\n\n{\"kanji\": \"道\", \"sentence\": \"道を歩く\", \"source\": \"x\"}\n";
+        let vocabs = read_vocab_jsonl(jsonl.as_bytes(), "a.jsonl").unwrap();
+        assert_eq!(
+            vocabs,
+            vec![vocab("友達", "友達に会う"), vocab("道", "道を歩く")]
+        );
+    }
+
+    #[test]
+    fn test_headwords_since() {
+        let dir = std::env::temp_dir().join(format!(
+            "gd2anki_test_headwords_since_{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let history = dir.join("history");
+        std::fs::write(
+            &history,
+            "1666000000 3 友達\n1666000100 3 尋ねる\n1666000200 3 道\n",
+        )
+        .unwrap();
+        let history = history.to_str().unwrap();
+
+        let words = read_headwords(history, InputFormat::History, Some(1666000100)).unwrap();
+        assert_eq!(words, vec!["尋ねる", "道"]);
+        let words = read_headwords(history, InputFormat::History, None).unwrap();
+        assert_eq!(words.len(), 3);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_text_headwords() {
+        let headwords: Vec<String> =
+            read_text_headwords("友達\n\n彼は駅で友達に会った\n".as_bytes(), "a.txt")
+                .collect::<Result<_>>()
+                .unwrap();
+        assert_eq!(headwords, vec!["友達", "彼は駅で友達に会った"]);
+    }
+}
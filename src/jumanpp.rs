@@ -0,0 +1,332 @@
+use log::warn;
+use std::fs;
+use std::process;
+
+use crate::analyzer::{Analyzer, CanonicalForm, Morpheme};
+use crate::error::{Error, Result};
+
+/// The Juman++ analyzer, run through the `jumanpp` binary on `PATH`.
+#[derive(Debug, Default)]
+pub struct Jumanpp;
+
+impl Analyzer for Jumanpp {
+    fn run(&self, sentences_path: &str, output_path: &str) -> Result<()> {
+        run_jumanpp(sentences_path, output_path)
+    }
+
+    fn parse(&self, output_path: &str) -> Result<Vec<Vec<Morpheme>>> {
+        parse_jumanpp_output(output_path)
+    }
+}
+
+/// Runs the `jumanpp` binary over `sentences_path` and waits for it to finish.
+pub fn run_jumanpp(sentences_path: &str, output_path: &str) -> Result<()> {
+    let status = process::Command::new("jumanpp")
+        .arg(sentences_path)
+        .arg("-o")
+        .arg(output_path)
+        .status()
+        .map_err(Error::io("jumanpp"))?;
+    if !status.success() {
+        return Err(Error::Command {
+            program: "jumanpp".to_string(),
+            status: status.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// One morpheme line of the Juman++ output with every field kept, ids
+/// included.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JumanppMorpheme {
+    pub surface: String,
+    pub reading: String,
+    pub lemma: String,
+    pub pos: String,
+    pub pos_id: u32,
+    pub sub_pos: String,
+    pub sub_pos_id: u32,
+    pub conjugation_type: String,
+    pub conjugation_type_id: u32,
+    pub conjugation_form: String,
+    pub conjugation_form_id: u32,
+    pub semantic_info: SemanticInfo,
+}
+
+/// The quoted semantic field at the end of a Juman++ line, e.g.
+/// `"代表表記:子供/こども カテゴリ:人 ドメイン:家庭・暮らし"`, or `NIL`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SemanticInfo {
+    pub representative: Option<CanonicalForm>, // 代表表記
+    pub categories: Vec<String>,               // カテゴリ
+    pub domains: Vec<String>,                  // ドメイン
+    pub others: Vec<(String, Option<String>)>, // e.g. 可能動詞:読む/よむ, 付属動詞候補（タ系）
+    pub raw: String,
+}
+
+/// Parses the semantic field of a Juman++ line, with or without its quotes.
+pub fn parse_semantic_info(field: &str) -> SemanticInfo {
+    let raw = field.trim_matches('"');
+    let mut info = SemanticInfo {
+        raw: raw.to_string(),
+        ..SemanticInfo::default()
+    };
+    if raw == "NIL" {
+        return info;
+    }
+
+    for item in raw.split(' ').filter(|item| !item.is_empty()) {
+        let (key, value) = match item.find(':') {
+            Some(i) => (&item[..i], Some(&item[i + 1..])),
+            None => (item, None),
+        };
+        match (key, value) {
+            ("代表表記", Some(value)) => {
+                // ambiguous words list every candidate, e.g. 辛い/からい?辛い/つらい
+                let first = value.split('?').next().unwrap_or(value);
+                if let Some(i) = first.find('/') {
+                    info.representative = Some(CanonicalForm {
+                        kanji: first[..i].to_string(),
+                        reading: first[i + 1..].to_string(),
+                    });
+                }
+            }
+            ("カテゴリ", Some(value)) => {
+                info.categories = value.split(';').map(str::to_string).collect()
+            }
+            ("ドメイン", Some(value)) => {
+                info.domains = value.split(';').map(str::to_string).collect()
+            }
+            _ => info
+                .others
+                .push((key.to_string(), value.map(str::to_string))),
+        }
+    }
+
+    info
+}
+
+/// Splits the eleven space separated fields off a Juman++ line, unescaping
+/// `\␣` and `\ ` to a space and `\\` to a backslash, and returns them with the
+/// rest of the line.
+fn split_jumanpp_fields(line: &str) -> (Vec<String>, &str) {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut chars = line.chars();
+
+    while fields.len() < 11 {
+        match chars.next() {
+            Some('\\') => match chars.next() {
+                Some('␣') => field.push(' '),
+                Some(c) => field.push(c),
+                None => field.push('\\'),
+            },
+            Some(' ') => fields.push(std::mem::take(&mut field)),
+            Some(c) => field.push(c),
+            None => {
+                if !field.is_empty() {
+                    fields.push(field);
+                }
+                break;
+            }
+        }
+    }
+
+    (fields, chars.as_str())
+}
+
+/// Parses one morpheme line of the Juman++ output into all of its fields,
+/// e.g. `尋ねて たずねて 尋ねる 動詞 2 * 0 母音動詞 1 タ系連用テ形 14 "代表表記:尋ねる/たずねる"`.
+/// A space in the text is written `\␣`.
+pub fn parse_jumanpp_morpheme(line: &str) -> Option<JumanppMorpheme> {
+    let (v, rest) = split_jumanpp_fields(line);
+    if v.len() < 11 {
+        return None;
+    }
+    let semantic_info = if rest.is_empty() { "NIL" } else { rest };
+
+    Some(JumanppMorpheme {
+        surface: v[0].clone(),
+        reading: v[1].clone(),
+        lemma: v[2].clone(),
+        pos: v[3].clone(),
+        pos_id: v[4].parse().ok()?,
+        sub_pos: v[5].clone(),
+        sub_pos_id: v[6].parse().ok()?,
+        conjugation_type: v[7].clone(),
+        conjugation_type_id: v[8].parse().ok()?,
+        conjugation_form: v[9].clone(),
+        conjugation_form_id: v[10].parse().ok()?,
+        semantic_info: parse_semantic_info(semantic_info),
+    })
+}
+
+impl From<JumanppMorpheme> for Morpheme {
+    fn from(m: JumanppMorpheme) -> Morpheme {
+        Morpheme {
+            surface: m.surface,
+            reading: m.reading,
+            lemma: m.lemma,
+            pos: m.pos,
+            sub_pos: m.sub_pos,
+            conjugation_type: m.conjugation_type,
+            conjugation_form: m.conjugation_form,
+            semantic_info: m.semantic_info.raw,
+            normalized_form: None,
+            canonical_form: m.semantic_info.representative,
+            alternatives: Vec::new(),
+        }
+    }
+}
+
+/// Parses one morpheme line of the Juman++ output, see
+/// `parse_jumanpp_morpheme`.
+pub fn parse_jumanpp_line(line: &str) -> Option<Morpheme> {
+    parse_jumanpp_morpheme(line).map(Morpheme::from)
+}
+
+/// Reads the Juman++ output at `output_path`, one `Vec` per `EOS`.
+///
+/// The alternative analyses Juman++ prints on `@` lines are kept in the
+/// `alternatives` of the morpheme before them.
+pub fn parse_jumanpp_output(output_path: &str) -> Result<Vec<Vec<Morpheme>>> {
+    let juman_output = fs::read_to_string(output_path).map_err(Error::io(output_path))?;
+
+    let mut sentences = Vec::new();
+    let mut sentence: Vec<Morpheme> = Vec::new();
+
+    for (line, x) in juman_output.lines().enumerate() {
+        if x == "EOS" {
+            sentences.push(sentence);
+            sentence = Vec::new();
+        } else if let Some(alternative) = x.strip_prefix("@ ") {
+            match (parse_jumanpp_line(alternative), sentence.last_mut()) {
+                (Some(morpheme), Some(last)) => last.alternatives.push(morpheme),
+                (Some(_), None) => warn!(
+                    "skipping {}",
+                    Error::line(output_path, line + 1, x, "alternative without a morpheme")
+                ),
+                (None, _) => warn!(
+                    "skipping {}",
+                    Error::line(output_path, line + 1, x, "not a morpheme line")
+                ),
+            }
+        } else {
+            match parse_jumanpp_line(x) {
+                Some(morpheme) => sentence.push(morpheme),
+                None => warn!(
+                    "skipping {}",
+                    Error::line(output_path, line + 1, x, "not a morpheme line")
+                ),
+            }
+        }
+    }
+
+    Ok(sentences)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_line() {
+        let m = parse_jumanpp_line(
+            "尋ねて たずねて 尋ねる 動詞 2 * 0 母音動詞 1 タ系連用テ形 14 \"代表表記:尋ねる/たずねる 付属動詞候補（タ系）\"",
+        )
+        .unwrap();
+        assert_eq!(m.surface, "尋ねて");
+        assert_eq!(m.reading, "たずねて");
+        assert_eq!(m.lemma, "尋ねる");
+        assert_eq!(m.pos, "動詞");
+        assert_eq!(m.sub_pos, "*");
+        assert_eq!(m.conjugation_type, "母音動詞");
+        assert_eq!(m.conjugation_form, "タ系連用テ形");
+        assert_eq!(
+            m.semantic_info,
+            "代表表記:尋ねる/たずねる 付属動詞候補（タ系）"
+        );
+        assert_eq!(
+            m.canonical_form,
+            Some(CanonicalForm {
+                kanji: "尋ねる".to_string(),
+                reading: "たずねる".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_morpheme() {
+        let m = parse_jumanpp_morpheme(
+            "子供 こども 子供 名詞 6 普通名詞 1 * 0 * 0 \"代表表記:子供/こども カテゴリ:人 ドメイン:家庭・暮らし;教育・学習\"",
+        )
+        .unwrap();
+        assert_eq!(m.pos_id, 6);
+        assert_eq!(m.sub_pos, "普通名詞");
+        assert_eq!(m.sub_pos_id, 1);
+        assert_eq!(m.semantic_info.categories, vec!["人"]);
+        assert_eq!(m.semantic_info.domains, vec!["家庭・暮らし", "教育・学習"]);
+    }
+
+    #[test]
+    fn test_parse_escaped_space() {
+        let m = parse_jumanpp_morpheme("\\␣ \\␣ \\␣ 特殊 1 空白 6 * 0 * 0 \"代表表記:\\␣/\\␣\"")
+            .unwrap();
+        assert_eq!(m.surface, " ");
+        assert_eq!(m.reading, " ");
+        assert_eq!(m.sub_pos, "空白");
+    }
+
+    #[test]
+    fn test_parse_semantic_info() {
+        let info = parse_semantic_info(
+            "\"代表表記:辛い/からい?辛い/つらい 可能動詞:読む/よむ 付属動詞候補（タ系）\"",
+        );
+        let representative = info.representative.unwrap();
+        assert_eq!(representative.kanji, "辛い");
+        assert_eq!(representative.reading, "からい");
+        assert_eq!(
+            info.others,
+            vec![
+                ("可能動詞".to_string(), Some("読む/よむ".to_string())),
+                ("付属動詞候補（タ系）".to_string(), None),
+            ]
+        );
+        assert_eq!(
+            parse_semantic_info("NIL"),
+            SemanticInfo {
+                raw: "NIL".to_string(),
+                ..SemanticInfo::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_short_line() {
+        assert_eq!(parse_jumanpp_line("彼 かれ 彼 名詞"), None);
+    }
+
+    #[test]
+    fn test_parse_output_alternatives() {
+        let path = std::env::temp_dir().join(format!(
+            "gd2anki_test_jumanpp_alternatives_{}.txt",
+            std::process::id()
+        ));
+        fs::write(
+            &path,
+            "辛い からい 辛い 形容詞 3 * 0 イ形容詞アウオ段 18 基本形 2 \"代表表記:辛い/からい\"\n\
+             @ 辛い つらい 辛い 形容詞 3 * 0 イ形容詞アウオ段 18 基本形 2 \"代表表記:辛い/つらい\"\n\
+             EOS\n",
+        )
+        .unwrap();
+        let sentences = parse_jumanpp_output(path.to_str().unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(sentences.len(), 1);
+        assert_eq!(sentences[0].len(), 1);
+        assert_eq!(sentences[0][0].reading, "からい");
+        assert_eq!(sentences[0][0].alternatives.len(), 1);
+        assert_eq!(sentences[0][0].alternatives[0].reading, "つらい");
+    }
+}
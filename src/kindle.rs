@@ -0,0 +1,150 @@
+use rusqlite::{Connection, OpenFlags, NO_PARAMS};
+use std::collections::HashMap;
+
+use crate::epub::Location;
+use crate::error::{Error, Result};
+use crate::gdict::Vocab;
+use crate::pairing::Pairing;
+
+/// One lookup of the Kindle Vocabulary Builder.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KindleLookup {
+    /// the word as selected in the book
+    pub word: String,
+    /// its dictionary form, as far as the Kindle knows
+    pub stem: String,
+    /// the sentence it was looked up in
+    pub usage: String,
+    pub book: Option<String>,
+    pub timestamp: i64, // unix milliseconds
+}
+
+impl KindleLookup {
+    /// The word the word list is matched against, its stem when there is one.
+    pub fn headword(&self) -> &str {
+        if self.stem.is_empty() {
+            &self.word
+        } else {
+            &self.stem
+        }
+    }
+}
+
+/// Reads the Japanese lookups of an open `vocab.db`, oldest first.
+pub fn query_kindle_lookups(conn: &Connection, path: &str) -> Result<Vec<KindleLookup>> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT w.word, w.stem, l.usage, b.title, l.timestamp
+            FROM LOOKUPS l
+            JOIN WORDS w ON l.word_key = w.id
+            LEFT JOIN BOOK_INFO b ON l.book_key = b.id
+            WHERE w.lang IS NULL OR w.lang LIKE 'ja%'
+            ORDER BY l.timestamp",
+        )
+        .map_err(Error::database(path))?;
+    let rows = stmt
+        .query_map(NO_PARAMS, |row| {
+            Ok(KindleLookup {
+                word: row.get::<_, Option<String>>(0)?.unwrap_or_default(),
+                stem: row.get::<_, Option<String>>(1)?.unwrap_or_default(),
+                usage: row.get::<_, Option<String>>(2)?.unwrap_or_default(),
+                book: row.get(3)?,
+                timestamp: row.get::<_, Option<i64>>(4)?.unwrap_or_default(),
+            })
+        })
+        .map_err(Error::database(path))?;
+
+    let mut lookups = Vec::new();
+    for lookup in rows {
+        let mut lookup = lookup.map_err(Error::database(path))?;
+        // one sentence per line in the sentence file
+        lookup.usage = lookup.usage.lines().map(str::trim).collect();
+        if !lookup.headword().trim().is_empty() {
+            lookups.push(lookup);
+        }
+    }
+    Ok(lookups)
+}
+
+/// Reads the Japanese lookups of the `vocab.db` at `path` without writing to
+/// it, it being the Kindle's own.
+pub fn read_kindle_lookups(path: &str) -> Result<Vec<KindleLookup>> {
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(Error::database(path))?;
+    query_kindle_lookups(&conn, path)
+}
+
+/// The lookups as word and sentence pairs. Lookups without a sentence are left
+/// out, there being nothing to pair them with.
+pub fn kindle_vocabs(lookups: &[KindleLookup]) -> Vec<Vocab> {
+    lookups
+        .iter()
+        .filter(|lookup| !lookup.usage.is_empty())
+        .map(|lookup| Vocab {
+            kanji: lookup.headword().to_string(),
+            sentence: lookup.usage.clone(),
+        })
+        .collect()
+}
+
+/// The book each sentence of `pairing` was looked up in.
+pub fn kindle_locations(lookups: &[KindleLookup], pairing: &Pairing) -> HashMap<usize, Location> {
+    let books: HashMap<&str, &str> = lookups
+        .iter()
+        .filter_map(|lookup| Some((lookup.usage.as_str(), lookup.book.as_deref()?)))
+        .collect();
+    pairing
+        .sentences
+        .iter()
+        .enumerate()
+        .filter_map(|(number, sentence)| {
+            let book = books.get(sentence.as_str())?;
+            Some((
+                number,
+                Location {
+                    book: book.to_string(),
+                    chapter: String::new(),
+                },
+            ))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pairing::{pair_headwords, PairingRules};
+
+    #[test]
+    fn test_kindle_lookups() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE WORDS (id TEXT PRIMARY KEY, word TEXT, stem TEXT, lang TEXT,
+                category INTEGER DEFAULT 0, timestamp INTEGER DEFAULT 0, profileid TEXT);
+            CREATE TABLE LOOKUPS (id TEXT PRIMARY KEY, word_key TEXT, book_key TEXT,
+                dict_key TEXT, pos TEXT, usage TEXT, timestamp INTEGER DEFAULT 0);
+            CREATE TABLE BOOK_INFO (id TEXT PRIMARY KEY, asin TEXT, guid TEXT, lang TEXT,
+                title TEXT, authors TEXT);
+            INSERT INTO WORDS VALUES ('ja:尋ねた', '尋ねた', '尋ねる', 'ja', 0, 0, '');
+            INSERT INTO WORDS VALUES ('ja:友達', '友達', '', 'ja', 0, 0, '');
+            INSERT INTO WORDS VALUES ('en:friend', 'friend', 'friend', 'en', 0, 0, '');
+            INSERT INTO BOOK_INFO VALUES ('b1', 'B0', 'g', 'ja', '鬼の話', '');
+            INSERT INTO LOOKUPS VALUES ('l1', 'ja:尋ねた', 'b1', '', '12', '道を
+尋ねた。', 2000);
+            INSERT INTO LOOKUPS VALUES ('l2', 'ja:友達', 'b1', '', '10', '友達に会った。', 1000);
+            INSERT INTO LOOKUPS VALUES ('l3', 'en:friend', 'b1', '', '11', 'a friend', 1500);",
+        )
+        .unwrap();
+
+        let lookups = query_kindle_lookups(&conn, "vocab.db").unwrap();
+        assert_eq!(lookups.len(), 2);
+        assert_eq!(lookups[0].headword(), "友達");
+        assert_eq!(lookups[1].headword(), "尋ねる");
+        assert_eq!(lookups[1].usage, "道を尋ねた。");
+
+        let pairing = pair_headwords(&[], &kindle_vocabs(&lookups), &PairingRules::default());
+        let locations = kindle_locations(&lookups, &pairing);
+        assert_eq!(locations.len(), 2);
+        assert_eq!(locations[&0].book, "鬼の話");
+    }
+}
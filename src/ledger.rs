@@ -0,0 +1,226 @@
+use rusqlite::{params, Connection, OptionalExtension, NO_PARAMS};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use crate::anki::AnkiExport;
+use crate::error::{Error, Result};
+
+/// Every card mined so far, kept in sqlite so that a rerun over the same
+/// lookups only exports the new ones.
+#[derive(Debug)]
+pub struct Ledger {
+    conn: Connection,
+    path: String,
+}
+
+impl Ledger {
+    /// Opens the ledger at `path`, creating it and its directory if needed.
+    pub fn open(path: &str) -> Result<Ledger> {
+        if let Some(dir) = Path::new(path)
+            .parent()
+            .filter(|d| !d.as_os_str().is_empty())
+        {
+            fs::create_dir_all(dir).map_err(Error::io(path))?;
+        }
+        let conn = Connection::open(path).map_err(Error::database(path))?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS mined (
+            id integer primary key,
+            lemma text not null,
+            reading text not null,
+            sentence text not null,
+            source text not null,
+            mined_at integer not null,
+            UNIQUE (lemma, reading, sentence)
+            )",
+            NO_PARAMS,
+        )
+        .map_err(Error::database(path))?;
+        Ok(Ledger {
+            conn,
+            path: path.to_string(),
+        })
+    }
+
+    /// When `lemma` read as `reading` was first mined from `sentence`, in unix
+    /// seconds.
+    pub fn mined_at(&self, lemma: &str, reading: &str, sentence: &str) -> Result<Option<i64>> {
+        mined_at(&self.conn, lemma, reading, sentence).map_err(Error::database(&self.path))
+    }
+
+    /// Turns a date sqlite understands, e.g. `2026-10-01` or
+    /// `2026-10-01 18:00`, into unix seconds.
+    pub fn timestamp(&self, date: &str) -> Result<Option<i64>> {
+        timestamp(&self.conn, date).map_err(Error::database(&self.path))
+    }
+
+    /// Keeps the exports never mined before, or first mined at or after
+    /// `since`. Nothing is recorded, see `record`.
+    pub fn filter_new(
+        &self,
+        exports: Vec<AnkiExport>,
+        since: Option<i64>,
+    ) -> Result<Vec<AnkiExport>> {
+        let mut seen = HashSet::new();
+        let mut ret = Vec::new();
+        for export in exports {
+            let word = &export.word;
+            let key = (
+                word.dictionary_form.clone(),
+                word.reading_kana.clone(),
+                export.sentence.clone(),
+            );
+            let is_new = match self.mined_at(&key.0, &key.1, &key.2)? {
+                Some(mined_at) => since.is_some_and(|since| mined_at >= since),
+                None => true,
+            };
+            // the same card twice in one run is exported once
+            if is_new && seen.insert(key) {
+                ret.push(export);
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Records the exports as mined from `source` now, unless they already
+    /// were. Called once they have been written or sent, so that a failed
+    /// run exports them again.
+    pub fn record(&mut self, exports: &[AnkiExport], source: &str) -> Result<()> {
+        let path = &self.path;
+        let tx = self.conn.transaction().map_err(Error::database(path))?;
+        for export in exports {
+            let word = &export.word;
+            record(
+                &tx,
+                &word.dictionary_form,
+                &word.reading_kana,
+                &export.sentence,
+                source,
+            )
+            .map_err(Error::database(path))?;
+        }
+        tx.commit().map_err(Error::database(path))
+    }
+}
+
+/// Turns a date sqlite understands into unix seconds, like
+/// `Ledger::timestamp`, for when there is no ledger at hand.
+pub fn parse_date(date: &str) -> Result<Option<i64>> {
+    let conn = Connection::open_in_memory().map_err(Error::database(":memory:"))?;
+    timestamp(&conn, date).map_err(Error::database(":memory:"))
+}
+
+fn timestamp(conn: &Connection, date: &str) -> rusqlite::Result<Option<i64>> {
+    conn.query_row(
+        "SELECT CAST(strftime('%s', ?1) AS INTEGER)",
+        params![date],
+        |row| row.get(0),
+    )
+}
+
+fn mined_at(
+    conn: &Connection,
+    lemma: &str,
+    reading: &str,
+    sentence: &str,
+) -> rusqlite::Result<Option<i64>> {
+    conn.query_row(
+        "SELECT mined_at FROM mined WHERE lemma=(?1) AND reading=(?2) AND sentence=(?3)",
+        params![lemma, reading, sentence],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+fn record(
+    conn: &Connection,
+    lemma: &str,
+    reading: &str,
+    sentence: &str,
+    source: &str,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR IGNORE INTO mined (lemma, reading, sentence, source, mined_at)
+        VALUES (?1, ?2, ?3, ?4, CAST(strftime('%s', 'now') AS INTEGER))",
+        params![lemma, reading, sentence, source],
+    )
+    .map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::anki::WordInformation;
+
+    fn export(word: &str, reading: &str, sentence: &str) -> AnkiExport {
+        AnkiExport {
+            word: WordInformation {
+                original: word.to_string(),
+                dictionary_form: word.to_string(),
+                reading_kana: reading.to_string(),
+                reading_furigana: reading.to_string(),
+                pos: "名詞".to_string(),
+                pos_information: "普通名詞".to_string(),
+                normalized_form: None,
+            },
+            sentence: sentence.to_string(),
+            sentence_furigana: sentence.to_string(),
+            sentence_number: 0,
+            cue: None,
+            region: None,
+            location: None,
+            sentence_audio: None,
+            picture: None,
+        }
+    }
+
+    #[test]
+    fn test_filter_new() {
+        let mut ledger = Ledger::open(":memory:").unwrap();
+        let first = ledger
+            .filter_new(
+                vec![
+                    export("友達", "ともだち", "友達に会う"),
+                    export("友達", "ともだち", "友達に会う"),
+                ],
+                None,
+            )
+            .unwrap();
+        assert_eq!(first.len(), 1);
+        // nothing is recorded until the cards are written
+        let again = ledger
+            .filter_new(vec![export("友達", "ともだち", "友達に会う")], None)
+            .unwrap();
+        assert_eq!(again.len(), 1);
+        ledger.record(&first, "a.xml").unwrap();
+
+        let second = ledger
+            .filter_new(
+                vec![
+                    export("友達", "ともだち", "友達に会う"),
+                    export("友達", "ともだち", "友達と話す"),
+                ],
+                None,
+            )
+            .unwrap();
+        assert_eq!(second.len(), 1);
+        assert_eq!(second[0].sentence, "友達と話す");
+
+        let all = ledger
+            .filter_new(
+                vec![export("友達", "ともだち", "友達に会う")],
+                Some(i64::MIN),
+            )
+            .unwrap();
+        assert_eq!(all.len(), 1);
+    }
+
+    #[test]
+    fn test_timestamp() {
+        let ledger = Ledger::open(":memory:").unwrap();
+        assert_eq!(ledger.timestamp("1970-01-02").unwrap(), Some(86400));
+        assert_eq!(ledger.timestamp("yesterday").unwrap(), None);
+        assert_eq!(parse_date("1970-01-02").unwrap(), Some(86400));
+    }
+}
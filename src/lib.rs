@@ -0,0 +1,62 @@
+//! Turns the words looked up in Goldendict into Anki mining cards.
+//!
+//! The pipeline is split into stages that can be used on their own:
+//!
+//! 1. [`gdict`] splits a Goldendict export or history into sentences and looked
+//!    up words, paired following the rules of [`pairing`]. [`input`] reads
+//!    plain text, TSV/CSV, JSONL and [`kindle`] lookups the same way, and [`subtitle`]
+//!    pairs looked up words with the subtitle lines they were heard in, as
+//!    [`mokuro`] does with the text boxes of OCRed manga, [`ruby`] with the
+//!    sentences of ruby annotated novels, [`epub`] with those of EPUB books
+//!    and [`texthooker`] with the lines hooked from visual novels.
+//! 2. An [`Analyzer`] such as [`jumanpp::Jumanpp`], [`mecab::Mecab`] or
+//!    [`sudachi::Sudachi`] splits the sentences into [`Morpheme`]s. With the
+//!    `builtin-tokenizer` feature, `builtin::Builtin` does so without any
+//!    external binary or dictionary, IPADIC being built in.
+//!    The author's ruby then corrects the readings of the analyzer.
+//! 3. [`mining`] matches the morphemes against the looked up words, producing
+//!    [`AnkiExport`]s, which the [`ledger`] narrows down to those not exported
+//!    by an earlier run. For subtitle cues, [`media`] cuts the sentence audio
+//!    and a screenshot out of the video with ffmpeg, and for manga it crops
+//!    the text box out of the page.
+//! 4. [`anki`] renders those as [`MiningCard`] rows, optionally with
+//!    frequencies from the database built by [`make_db`] and read by
+//!    [`get_freq`], or [`apkg`] writes them straight into a deck with their
+//!    media, or [`ankiconnect`] adds them to a running Anki.
+//!
+//! Readings and furigana are derived by [`furigana`], and [`utils`] has the
+//! mora helpers.
+
+pub mod analyzer;
+pub mod anki;
+pub mod ankiconnect;
+pub mod apkg;
+#[cfg(feature = "builtin-tokenizer")]
+pub mod builtin;
+pub mod diff;
+pub mod epub;
+pub mod error;
+pub mod furigana;
+pub mod gdict;
+pub mod get_freq;
+pub mod input;
+pub mod jumanpp;
+pub mod kindle;
+pub mod ledger;
+pub mod make_db;
+pub mod mecab;
+pub mod media;
+pub mod mining;
+pub mod mokuro;
+pub mod pairing;
+pub mod ruby;
+pub mod subtitle;
+pub mod sudachi;
+pub mod texthooker;
+pub mod utils;
+
+pub use analyzer::{Analyzer, CanonicalForm, Morpheme};
+pub use anki::{AnkiExport, MiningCard, WordInformation};
+pub use error::{Error, Result};
+pub use furigana::{get_canonical_reading, get_furigana_reading, get_reading_stem, AnkiReading};
+pub use get_freq::{get_freq_2016, get_freq_anime_jdrama, get_freq_narou};
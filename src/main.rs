@@ -1,497 +1,857 @@
-use colored::Colorize;
-use csv::{ReaderBuilder, WriterBuilder};
-use difference::{Changeset, Difference};
-use quick_xml::de::from_str;
-use regex::Regex;
-use serde::{Deserialize, Serialize};
-use std::fs;
+use anyhow::{Context, Result};
+use clap::{crate_authors, crate_description, crate_version, Arg, ArgMatches, Command};
+use fern::colors::{Color, ColoredLevelConfig};
+use log::{info, warn};
+use std::convert::TryFrom;
 use std::fs::OpenOptions;
 use std::io::Write;
-use std::process::Command;
 use xkcd_unreachable::xkcd_unreachable;
 
-mod get_freq;
-use get_freq::get_freq_2016;
-
-mod make_db;
-use make_db::parse_wadoku_xml;
-
-mod utils;
+use gd2anki::anki::{make_mining_csv, make_mining_notes, AnkiExport};
+use gd2anki::ankiconnect::{default_field_map, parse_field_map, AnkiConnect, Outcome, DEFAULT_URL};
+use gd2anki::apkg::{write_apkg, MODEL_NAME};
+#[cfg(feature = "builtin-tokenizer")]
+use gd2anki::builtin::Builtin;
+use gd2anki::diff::get_diff;
+use gd2anki::epub::{book_name, pair_book, read_epub, read_locations, write_locations};
+use gd2anki::gdict::{default_history_path, read_vocab_pairs};
+use gd2anki::input::{parse_input, read_headwords, read_timed_headwords, InputFormat};
+use gd2anki::jumanpp::Jumanpp;
+use gd2anki::kindle::{kindle_locations, read_kindle_lookups};
+use gd2anki::ledger::{parse_date, Ledger};
+use gd2anki::make_db::{make_freq_2016_ja, make_freq_db, make_narou_db, parse_wadoku_xml};
+use gd2anki::mecab::{Mecab, MecabDictionary};
+use gd2anki::media::MediaCutter;
+use gd2anki::mining::mine_sentences;
+use gd2anki::mokuro::{pair_boxes, read_mokuro, read_regions, write_regions};
+use gd2anki::pairing::{Decision, Pairing, PairingRules};
+use gd2anki::ruby::{
+    apply_reading_hints, pair_sentences, read_reading_hints, read_ruby_text, write_reading_hints,
+};
+use gd2anki::subtitle::{pair_subtitles, read_cues, read_subtitles, write_cues};
+use gd2anki::sudachi::{SplitMode, Sudachi};
+use gd2anki::texthooker::{pair_log, read_log};
+use gd2anki::{Analyzer, Error};
+
+const PROGRAM_NAME: &str = "gd2anki";
+
+fn setup_logging() -> Result<()> {
+    let colors = ColoredLevelConfig::new()
+        .warn(Color::Yellow)
+        .error(Color::Red);
+    fern::Dispatch::new()
+        .format(move |out, message, record| {
+            out.finish(format_args!(
+                "[{}] {}",
+                colors.color(record.level()),
+                message
+            ))
+        })
+        .level(log::LevelFilter::Info)
+        .chain(std::io::stderr())
+        .apply()?;
+    Ok(())
+}
 
-#[derive(Debug, Deserialize)]
-struct Vocab {
-    kanji: String,
-    sentence: String,
+fn write_output(text: &str, output_path: Option<&str>) -> Result<()> {
+    match output_path {
+        Some(path) => {
+            let mut output_file = OpenOptions::new()
+                .write(true)
+                .truncate(true)
+                .create(true)
+                .open(path)
+                .map_err(Error::io(path))?;
+            output_file
+                .write_all(text.as_bytes())
+                .map_err(Error::io(path))?;
+        }
+        None => println!("{}", text),
+    }
+    Ok(())
 }
 
-struct StemOkurigana {
-    stem: String,
-    conjugation_part: String,
-    okurigana: String,
+fn path_arg(name: &'static str, default: &'static str, help: &'static str) -> Arg<'static> {
+    Arg::new(name)
+        .long(name)
+        .takes_value(true)
+        .value_name("FILE")
+        .default_value(default)
+        .help(help)
 }
 
-fn get_stem_okurigana(original: &str, dictionary_form: &str) -> StemOkurigana {
-    if original == dictionary_form {
-        return StemOkurigana {
-            stem: original.to_string(),
-            conjugation_part: "".to_string(),
-            okurigana: "".to_string(),
-        };
-    } else {
-        let changeset = Changeset::new(original, dictionary_form, "");
-
-        let mut stem = "".to_string();
-        let mut conjugation_part = "".to_string();
-        let mut okurigana = "".to_string();
-
-        for diff in changeset.diffs {
-            if let Difference::Same(text) = diff {
-                stem = text;
-            } else if let Difference::Rem(text) = diff {
-                conjugation_part = text;
-            } else if let Difference::Add(text) = diff {
-                okurigana = text;
-            }
-        }
+fn input_args() -> [Arg<'static>; 4] {
+    [
+        Arg::new("input")
+            .help("the xml output file from goldendict or other lookups, - for stdin")
+            .required_unless_present("history"),
+        Arg::new("format")
+            .long("format")
+            .takes_value(true)
+            .possible_values(["gdict", "history", "text", "tsv", "csv", "jsonl", "kindle"])
+            .help("the format of the input, guessed from its extension by default"),
+        Arg::new("history")
+            .long("history")
+            .conflicts_with("format")
+            .help("read goldendict's own history file, by default from its config directory"),
+        Arg::new("lookups-since")
+            .long("lookups-since")
+            .takes_value(true)
+            .value_name("DATE")
+            .help("only read the lookups made on or after DATE, e.g. 2026-10-01 18:00, when the history or vocab.db records it"),
+    ]
+}
 
-        return StemOkurigana {
-            stem: stem,
-            conjugation_part: conjugation_part,
-            okurigana: okurigana,
-        };
-    }
+fn vocabs_arg() -> Arg<'static> {
+    path_arg(
+        "vocabs",
+        "vocabs.txt",
+        "looked up words numbered by sentence",
+    )
 }
 
-struct AnkiReading {
-    // NOTE: adds "る" to verb, "だ" and whatever undesired stuff are removed
-    word: String,
-    furigana: String,
-    kana: String,
+fn sentences_arg() -> Arg<'static> {
+    path_arg(
+        "sentences",
+        "sentences.txt",
+        "one sentence per line, fed to the analyzer",
+    )
 }
 
-fn get_reading_stem(
-    original: &str,
-    yomi_original: &str,
-    dictionary_form: &str,
-    word_pos: &str,
-) -> AnkiReading {
-    if original == dictionary_form {
-        return AnkiReading {
-            word: original.to_string(),
-            furigana: get_furigana_reading(original, yomi_original, false),
-            kana: yomi_original.to_string(),
-        };
-    }
+fn pairing_args() -> [Arg<'static>; 7] {
+    [
+        Arg::new("max-word-len")
+            .long("max-word-len")
+            .takes_value(true)
+            .value_name("N")
+            .default_value("7")
+            .validator(|v| v.parse::<usize>())
+            .help("headwords longer than N characters are sentences, 0 turns this off"),
+        Arg::new("sentence-punctuation")
+            .long("sentence-punctuation")
+            .help("headwords ending in 。, ！, ？ or 」 are sentences"),
+        Arg::new("clause-punctuation")
+            .long("clause-punctuation")
+            .help("headwords containing 。 or 、 are sentences"),
+        Arg::new("marker")
+            .long("marker")
+            .takes_value(true)
+            .value_name("PREFIX")
+            .help("headwords starting with PREFIX are sentences"),
+        Arg::new("words-before-sentence")
+            .long("words-before-sentence")
+            .help("pair words with the sentence looked up after them"),
+        Arg::new("pairs")
+            .long("pairs")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("explicit <vocab><kanji/><sentence/></vocab> pairs to add"),
+        path_arg(
+            "pairing-report",
+            "pairing.txt",
+            "why each headword became a sentence or a word",
+        ),
+    ]
+}
 
-    let tmp = get_stem_okurigana(original, dictionary_form);
-    let mut stem = tmp.stem;
+fn subtitle_args() -> [Arg<'static>; 2] {
+    [
+        Arg::new("subtitles")
+            .long("subtitles")
+            .takes_value(true)
+            .value_name("FILE")
+            .help(
+                "pair the looked up words of the input with the lines of this srt, ass or vtt file",
+            ),
+        path_arg(
+            "cues",
+            "cues.tsv",
+            "the timing of each sentence taken from the subtitles",
+        ),
+    ]
+}
 
-    let conjugation_part = tmp.conjugation_part;
-    let mut okurigana = tmp.okurigana;
+fn mokuro_args() -> [Arg<'static>; 3] {
+    [
+        Arg::new("mokuro")
+            .long("mokuro")
+            .takes_value(true)
+            .value_name("FILE")
+            .conflicts_with("subtitles")
+            .help("pair the looked up words of the input with the text boxes of this .mokuro volume, page json or directory of page jsons"),
+        Arg::new("images")
+            .long("images")
+            .takes_value(true)
+            .value_name("DIR")
+            .help("the page images of the manga, by default found next to its ocr"),
+        path_arg(
+            "regions",
+            "regions.tsv",
+            "the text box of each sentence taken from the manga",
+        ),
+    ]
+}
 
-    // orig: 痛快, dict: 痛快だ
-    if conjugation_part == "" && okurigana != "" {
-        if okurigana == "だ" && word_pos == "形容詞" {
-            okurigana = "".to_string();
-        }
-        return AnkiReading {
-            word: format!("{}{}", stem, okurigana),
-            furigana: format!(
-                "{}{}",
-                get_furigana_reading(original, yomi_original, false),
-                okurigana
-            ),
-            kana: format!("{}{}", yomi_original.to_string(), okurigana),
-        };
-    } else if conjugation_part != "" && okurigana != "" {
-        // orig: 尋ねて, dict: 尋ねる
-        let yomi_rev = yomi_original.chars().rev().collect::<String>();
-        let conjugation_part_rev = conjugation_part.chars().rev().collect::<String>();
+fn ruby_args() -> [Arg<'static>; 2] {
+    [
+        Arg::new("ruby")
+            .long("ruby")
+            .takes_value(true)
+            .value_name("FILE")
+            .conflicts_with_all(&["subtitles", "mokuro"])
+            .help("pair the looked up words of the input with the sentences of this aozora bunko or narou text, whose ruby corrects the readings"),
+        path_arg(
+            "readings",
+            "readings.tsv",
+            "the reading of each word annotated with ruby",
+        ),
+    ]
+}
 
-        let re = Regex::new(format!("{}", conjugation_part_rev).as_str()).unwrap();
-        let kana_stem = re.replace(&yomi_rev, "").chars().rev().collect::<String>();
+fn epub_args() -> [Arg<'static>; 2] {
+    [
+        Arg::new("epub")
+            .long("epub")
+            .takes_value(true)
+            .value_name("FILE")
+            .conflicts_with_all(&["subtitles", "mokuro", "ruby"])
+            .help("pair the looked up words of the input with the sentences of this epub, whose ruby corrects the readings"),
+        path_arg(
+            "locations",
+            "locations.tsv",
+            "the book and chapter of each sentence taken from the epub or kindle",
+        ),
+    ]
+}
 
-        if okurigana == "だ" && word_pos == "形容詞" {
-            okurigana = "".to_string();
-        }
+fn log_arg() -> Arg<'static> {
+    Arg::new("log")
+        .long("log")
+        .takes_value(true)
+        .value_name("FILE")
+        .conflicts_with_all(&["subtitles", "mokuro", "ruby", "epub"])
+        .help("pair the looked up words of the input with the lines of this textractor or texthooker log, by time when both have it")
+}
 
-        return AnkiReading {
-            word: format!("{}{}", stem, okurigana),
-            furigana: format!(
-                "{}{}",
-                get_furigana_reading(&stem, &kana_stem, false),
-                okurigana
-            ),
-            kana: format!("{}{}", kana_stem, okurigana),
+/// The lookups to read and their format, Goldendict's own history by default
+/// with --history.
+fn input_of(matches: &ArgMatches) -> Result<(String, InputFormat)> {
+    Ok(if matches.is_present("history") {
+        let history_path = match matches.value_of("input") {
+            Some(path) => path.to_string(),
+            None => default_history_path()
+                .context("could not find goldendict's history file, pass it as input")?
+                .to_string_lossy()
+                .into_owned(),
         };
+        info!("reading the history at {}", history_path);
+        (history_path, InputFormat::History)
     } else {
-        //orig: 空白だった, yomi: くうはくだった, dict: 空白だ, pos: 形容詞
-        //stem: 空白だ, conjugation_part: った, okurigana:
-        let yomi_rev = yomi_original.chars().rev().collect::<String>();
-        let orig_rev = original.chars().rev().collect::<String>();
-
-        let changeset = Changeset::new(&yomi_rev, &orig_rev, "");
-        let mut kana_stem = "".to_string();
-        for diff in changeset.diffs {
-            if let Difference::Rem(text) = diff {
-                kana_stem = text.chars().rev().collect::<String>();
-            }
-        }
-
-        let mut dict_santinize = stem.clone();
-        let try_pop = stem.pop();
-
-        if try_pop == Some("だ".parse::<char>().unwrap()) && word_pos == "形容詞" {
-            dict_santinize.pop();
-        }
-
-        return AnkiReading {
-            word: format!("{}{}", dict_santinize, okurigana),
-            furigana: format!(
-                "{}{}",
-                get_furigana_reading(&dict_santinize, &kana_stem, false),
-                okurigana
-            ),
-            kana: format!("{}{}", kana_stem, okurigana),
+        let input = matches.value_of("input").unwrap();
+        let format = match matches.value_of("format") {
+            Some("history") => InputFormat::History,
+            Some("text") => InputFormat::Text,
+            Some("tsv") => InputFormat::Tsv,
+            Some("csv") => InputFormat::Csv,
+            Some("jsonl") => InputFormat::Jsonl,
+            Some("kindle") => InputFormat::Kindle,
+            Some(_) => InputFormat::Gdict,
+            None if input == "-" => anyhow::bail!("pass --format to read from stdin"),
+            None => InputFormat::from_path(input).unwrap_or(InputFormat::Gdict),
         };
-    }
+        (input.to_string(), format)
+    })
 }
 
-fn get_furigana_reading(kanji: &str, yomi: &str, plain_text: bool) -> String {
-    let mut text = String::new();
-
-    let changeset = Changeset::new(kanji, yomi, "");
-    for (i, _x) in changeset.diffs.iter().enumerate() {
-        if let Difference::Rem(kanji) = &changeset.diffs[i] {
-            if let Difference::Add(furigana) = &changeset.diffs[i + 1] {
-                if plain_text {
-                    // THIS DOES NOT WORK WITH ANKI
-                    text += format!("{}[{}]", kanji, furigana).as_str();
-                } else {
-                    text += format!("<ruby><rb>{}<rt>{}</ruby>", kanji, furigana).as_str();
-                }
-            } else if let Difference::Same(furigana) = &changeset.diffs[i + 1] {
-                if plain_text {
-                    // THIS DOES NOT WORK WITH ANKI
-                    text += format!("{}[{}]", kanji, furigana).as_str();
-                } else {
-                    text += format!("<ruby><rb>{}<rt>{}</ruby>", kanji, furigana).as_str();
-                }
-            }
-        } else if let Difference::Same(same) = &changeset.diffs[i] {
-            text += same;
+fn extract(matches: &ArgMatches) -> Result<()> {
+    let rules = PairingRules {
+        max_word_len: match matches.value_of_t("max-word-len")? {
+            0 => None,
+            len => Some(len),
+        },
+        sentence_punctuation: matches.is_present("sentence-punctuation"),
+        clause_punctuation: matches.is_present("clause-punctuation"),
+        marker: matches.value_of("marker").map(str::to_string),
+        words_before_sentence: matches.is_present("words-before-sentence"),
+    };
+    let explicit = match matches.value_of("pairs") {
+        Some(path) => read_vocab_pairs(path)?,
+        None => Vec::new(),
+    };
+    let report_path = matches.value_of("pairing-report").unwrap();
+
+    let (input, format) = input_of(matches)?;
+    let since = match matches.value_of("lookups-since") {
+        Some(date) => Some(
+            parse_date(date)?
+                .and_then(|t| u64::try_from(t).ok())
+                .with_context(|| format!("not a date: {}", date))?,
+        ),
+        None => None,
+    };
+
+    let write_pairing = |pairing: &Pairing| {
+        pairing.write(
+            matches.value_of("vocabs").unwrap(),
+            matches.value_of("sentences").unwrap(),
+            Some(report_path),
+        )
+    };
+    let pairing = if let Some(subtitles) = matches.value_of("subtitles") {
+        let words = read_headwords(&input, format, since)?;
+        let (pairing, cues) = pair_subtitles(&read_subtitles(subtitles)?, subtitles, &words);
+        write_pairing(&pairing)?;
+        write_cues(matches.value_of("cues").unwrap(), &cues)?;
+        pairing
+    } else if let Some(mokuro) = matches.value_of("mokuro") {
+        let words = read_headwords(&input, format, since)?;
+        let boxes = read_mokuro(mokuro, matches.value_of("images"))?;
+        let (pairing, regions) = pair_boxes(&boxes, &words);
+        write_pairing(&pairing)?;
+        write_regions(matches.value_of("regions").unwrap(), &regions)?;
+        pairing
+    } else if let Some(ruby) = matches.value_of("ruby") {
+        let words = read_headwords(&input, format, since)?;
+        let (sentences, hints) = read_ruby_text(ruby)?;
+        let pairing = pair_sentences(&sentences, ruby, &words);
+        write_pairing(&pairing)?;
+        write_reading_hints(matches.value_of("readings").unwrap(), &hints)?;
+        pairing
+    } else if let Some(epub) = matches.value_of("epub") {
+        let words = read_headwords(&input, format, since)?;
+        let book = read_epub(epub)?;
+        let (pairing, locations) = pair_book(&book, &book_name(epub), &words);
+        write_pairing(&pairing)?;
+        write_reading_hints(matches.value_of("readings").unwrap(), &book.hints)?;
+        write_locations(matches.value_of("locations").unwrap(), &locations)?;
+        pairing
+    } else if let Some(log) = matches.value_of("log") {
+        let lookups = read_timed_headwords(&input, format, since)?;
+        let pairing = pair_log(&read_log(log)?, log, &lookups);
+        write_pairing(&pairing)?;
+        pairing
+    } else {
+        let pairing = parse_input(&input, format, since, &rules, &explicit)?;
+        write_pairing(&pairing)?;
+        if format == InputFormat::Kindle {
+            let locations = kindle_locations(&read_kindle_lookups(&input)?, &pairing);
+            write_locations(matches.value_of("locations").unwrap(), &locations)?;
+        }
+        pairing
+    };
+    for decision in &pairing.decisions {
+        if let Decision::Dropped { .. } = decision {
+            warn!("{}", decision);
         }
     }
-    text
+    info!(
+        "paired {} words with {} sentences, see {}",
+        pairing.vocabs.len(),
+        pairing.sentences.len(),
+        report_path
+    );
+    Ok(())
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
-struct GdictRoot {
-    headword: Vec<String>,
+const ANALYZERS: &[&str] = &[
+    "jumanpp",
+    "mecab",
+    "mecab-unidic",
+    "sudachi",
+    #[cfg(feature = "builtin-tokenizer")]
+    "builtin",
+];
+
+fn analyzer_args() -> [Arg<'static>; 4] {
+    [
+        path_arg(
+            "analysis",
+            "analysis.txt",
+            "the output file of the analyzer",
+        )
+        .alias("jumanpp"),
+        Arg::new("analyzer")
+            .long("analyzer")
+            .takes_value(true)
+            .possible_values(ANALYZERS)
+            .default_value("jumanpp")
+            .help("the morphological analyzer to run or whose output to read"),
+        Arg::new("dicdir")
+            .long("dicdir")
+            .takes_value(true)
+            .value_name("DIR")
+            .help("the dictionary directory of mecab"),
+        Arg::new("split-mode")
+            .long("split-mode")
+            .takes_value(true)
+            .possible_values(["A", "B", "C"])
+            .default_value("C")
+            .help("how finely sudachi splits compounds, A being the finest"),
+    ]
 }
 
-fn parse_gdict_xml_output(path: &str) {
-    let s = fs::read_to_string(path).unwrap();
-
-    let root: GdictRoot = from_str(&s).expect("something went wrong deser");
-    let mut vocabs = String::new();
-    let mut sentences = String::new();
-    let mut sentence_count = -1;
-
-    for (_i, x) in root.headword.iter().enumerate() {
-        let v: Vec<char> = x.chars().collect();
-        if v.len() > 7 {
-            sentence_count += 1;
-            sentences += format!("{}\n", x).as_str();
-        } else {
-            vocabs += format!("{:04} {}\n", sentence_count, x).as_str();
-        }
-    }
-    let mut vocab_file = OpenOptions::new()
-        .write(true)
-        .truncate(true)
-        .create(true)
-        .open("vocabs.txt")
-        .unwrap();
-    vocab_file.write_all(vocabs.as_bytes()).unwrap();
-
-    let mut sentence_file = OpenOptions::new()
-        .write(true)
-        .truncate(true)
-        .create(true)
-        .open("sentences.txt")
-        .unwrap();
-    sentence_file.write_all(sentences.as_bytes()).unwrap();
+fn get_analyzer(matches: &ArgMatches) -> Result<Box<dyn Analyzer>> {
+    let dicdir = matches.value_of("dicdir").map(str::to_string);
+    Ok(match matches.value_of("analyzer") {
+        Some("mecab") => Box::new(Mecab {
+            dictionary: MecabDictionary::Ipadic,
+            dicdir,
+        }),
+        Some("mecab-unidic") => Box::new(Mecab {
+            dictionary: MecabDictionary::Unidic,
+            dicdir,
+        }),
+        Some("sudachi") => Box::new(Sudachi {
+            split_mode: match matches.value_of("split-mode") {
+                Some("A") => SplitMode::A,
+                Some("B") => SplitMode::B,
+                _ => SplitMode::C,
+            },
+        }),
+        #[cfg(feature = "builtin-tokenizer")]
+        Some("builtin") => Box::new(Builtin),
+        _ => Box::new(Jumanpp),
+    })
 }
 
-#[derive(Debug)]
-struct AnkiExport {
-    word: WordInformation,
-    sentence: String,
-    sentence_furigana: String,
+fn export_args() -> [Arg<'static>; 7] {
+    [
+        path_arg(
+            "debug",
+            "debug.txt",
+            "trace of every token tried against the vocabs",
+        ),
+        path_arg("result", "result.txt", "matched words numbered by sentence"),
+        Arg::new("output")
+            .short('o')
+            .long("output")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("write the csv here instead of stdout"),
+        Arg::new("freq-db")
+            .long("freq-db")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("fill the frequency fields from this database (see build-db)"),
+        path_arg(
+            "ledger",
+            "database/ledger.db",
+            "every card exported so far, only new ones are exported again",
+        ),
+        Arg::new("since")
+            .long("since")
+            .takes_value(true)
+            .value_name("DATE")
+            .conflicts_with("all")
+            .help("also export again the cards first exported on or after DATE, e.g. 2026-10-01"),
+        Arg::new("all")
+            .long("all")
+            .help("export every card, even those already in the ledger"),
+    ]
 }
 
-#[derive(Debug)]
-struct WordInformation {
-    original: String,
-    dictionary_form: String,
-    reading_kana: String,     // for dictionary form, not original
-    reading_furigana: String, // for dictionary form, not original
-    pos: String,
-    pos_information: String,
+fn deck_args() -> [Arg<'static>; 5] {
+    [
+        Arg::new("apkg")
+            .long("apkg")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("write an anki deck with the note type and media instead of the csv"),
+        Arg::new("anki-connect")
+            .long("anki-connect")
+            .takes_value(true)
+            .value_name("URL")
+            .min_values(0)
+            .default_missing_value(DEFAULT_URL)
+            .conflicts_with("apkg")
+            .help("add the cards to the running anki through AnkiConnect instead of printing the csv"),
+        Arg::new("deck")
+            .long("deck")
+            .takes_value(true)
+            .value_name("NAME")
+            .default_value("Mining")
+            .help("the deck the cards of --apkg or --anki-connect go into"),
+        Arg::new("note-type")
+            .long("note-type")
+            .takes_value(true)
+            .value_name("NAME")
+            .default_value(MODEL_NAME)
+            .help("the note type --anki-connect adds, created when it is the default one"),
+        Arg::new("fields")
+            .long("fields")
+            .takes_value(true)
+            .value_name("MAP")
+            .help("the note type fields --anki-connect fills, e.g. VocabKanji=Word,Sentence=Front, by default each mining field into the one of the same name"),
+    ]
 }
 
-fn parse_jumanpp_output(
-    output_path: &str,
-    word_list: &str,
-    debug_path: &str,
-    result_counter_path: &str,
-) -> Vec<AnkiExport> {
-    let juman_output = fs::read_to_string(output_path).unwrap();
-    let words_string = fs::read_to_string(word_list).unwrap();
-
-    let re_ignore = Regex::new(r"^@").unwrap(); // ignore line starts with @
-    let mut current_sentence_count = 0;
-    let mut sentence = String::new();
-    let mut sentence_furigana = String::new();
-    let mut saved_words_information: Vec<WordInformation> = Vec::new();
-    let mut dedupe_vec = Vec::new();
-
-    let mut ret: Vec<AnkiExport> = Vec::new();
-    let mut debug_text = String::new();
-    let mut result_counter_text = String::new();
-
-    for (_i, x) in juman_output.lines().enumerate() {
-        if x == "EOS" {
-            for word in saved_words_information {
-                ret.push(AnkiExport {
-                    word: word,
-                    sentence: format!("{}", sentence).to_string(),
-                    sentence_furigana: format!("{}", sentence_furigana).to_string(),
-                });
-            }
-            current_sentence_count += 1;
-            // reset
-            sentence = "".to_string();
-            sentence_furigana = "".to_string();
-            saved_words_information = Vec::new();
-        } else if !re_ignore.is_match(x) {
-            let v: Vec<&str> = x.split(" ").collect();
-
-            let dictionary_form_sanitized = &mut v[2].to_string();
-            if v[3] == "形容詞" || v[3] == "助動詞" {
-                // adjective or aux verb
-                dictionary_form_sanitized.pop();
-            }
-            let tmp_re = Regex::new(
-                format!(
-                    "{} ({}|{})\n",
-                    current_sentence_count, v[0], dictionary_form_sanitized
-                )
-                .as_str(),
-            )
-            .unwrap();
-
-            debug_text += format!(
-                "current sentence_count: {:04}, v[0]: {}, current regex: {}",
-                current_sentence_count, v[0], tmp_re
-            )
-            .as_str();
-
-            if tmp_re.is_match(&words_string) && !dedupe_vec.contains(&v[0]) {
-                debug_text += format!(
-                    "GOT THROUGH => sentence_count: {:04}, v[0]: {}, current regex: {}",
-                    current_sentence_count, v[0], tmp_re
-                )
-                .as_str();
-                result_counter_text += format!("{:04} {}\n", current_sentence_count, v[2]).as_str();
-                dedupe_vec.push(&v[0]);
-
-                let p = get_reading_stem(&v[0], &v[1], &v[2], &v[3]);
-                saved_words_information.push(WordInformation {
-                    original: v[0].to_string(),
-                    dictionary_form: p.word,
-                    reading_kana: p.kana,
-                    reading_furigana: p.furigana,
-                    pos: v[3].to_string(),
-                    pos_information: v[5].to_string(),
-                });
-            }
-
-            let kanji_count = v[0]
-                .chars()
-                .filter(kanji::is_kanji)
-                .collect::<Vec<char>>()
-                .len();
+fn media_args() -> [Arg<'static>; 3] {
+    [
+        Arg::new("media-dir")
+            .long("media-dir")
+            .takes_value(true)
+            .value_name("DIR")
+            .help("cut the sentence audio and a screenshot of each subtitle cue, or the text box of each manga page, into DIR, usually anki's collection.media"),
+        Arg::new("video")
+            .long("video")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("the video to cut from, by default the one named like the subtitles"),
+        Arg::new("padding")
+            .long("padding")
+            .takes_value(true)
+            .value_name("MS")
+            .default_value("250")
+            .help("milliseconds of audio kept before and after each cue"),
+    ]
+}
 
-            sentence += &v[0];
+fn analyze(matches: &ArgMatches) -> Result<()> {
+    get_analyzer(matches)?.run(
+        matches.value_of("sentences").unwrap(),
+        matches.value_of("analysis").unwrap(),
+    )?;
+    Ok(())
+}
 
-            if kanji_count > 0 {
-                sentence_furigana += &get_furigana_reading(&v[0], &v[1], false);
-            } else {
-                sentence_furigana += &v[0];
-            }
+fn export(
+    matches: &ArgMatches,
+    source: &str,
+    cues_path: Option<&str>,
+    regions_path: Option<&str>,
+    readings_path: Option<&str>,
+    locations_path: Option<&str>,
+) -> Result<()> {
+    let mut sentences = get_analyzer(matches)?.parse(matches.value_of("analysis").unwrap())?;
+    if let Some(readings_path) = readings_path {
+        let corrected = apply_reading_hints(&mut sentences, &read_reading_hints(readings_path)?);
+        info!("{} readings corrected by the ruby", corrected);
+    }
+    let mut v: Vec<AnkiExport> = mine_sentences(
+        &sentences,
+        matches.value_of("vocabs").unwrap(),
+        matches.value_of("debug").unwrap(),
+        matches.value_of("result").unwrap(),
+    )?;
+    if let Some(cues_path) = cues_path {
+        let cues = read_cues(cues_path)?;
+        for export in &mut v {
+            export.cue = cues.get(&export.sentence_number).cloned();
         }
     }
-
-    let mut debug_file = OpenOptions::new()
-        .write(true)
-        .truncate(true)
-        .create(true)
-        .open(debug_path)
-        .unwrap();
-    debug_file.write_all(debug_text.as_bytes()).unwrap();
-
-    let mut result_counter_file = OpenOptions::new()
-        .write(true)
-        .truncate(true)
-        .create(true)
-        .open(result_counter_path)
-        .unwrap();
-    result_counter_file
-        .write_all(result_counter_text.as_bytes())
-        .unwrap();
-
-    ret
-}
-
-fn easy_counter_diff(file_path: &str) -> String {
-    let s = fs::read_to_string(file_path).unwrap();
-    let mut current_sentence_count = 0;
-    let mut ret = String::new();
-
-    let re = Regex::new(r"(\d+)").unwrap();
-    let caps = re.captures_iter(&s);
-    let mut count = 0;
-    for cap in caps {
-        if cap[1].parse::<i32>().unwrap() == current_sentence_count {
-            count += 1;
-        } else {
-            ret += format!("{:04} {}\n", current_sentence_count, count).as_str();
-            count = 0;
-            current_sentence_count += 1;
+    if let Some(locations_path) = locations_path {
+        let locations = read_locations(locations_path)?;
+        for export in &mut v {
+            export.location = locations.get(&export.sentence_number).cloned();
+        }
+    }
+    if let Some(regions_path) = regions_path {
+        let regions = read_regions(regions_path)?;
+        for export in &mut v {
+            export.region = regions.get(&export.sentence_number).cloned();
         }
     }
-    format!("{}{:04} {}", ret, current_sentence_count, count)
-}
 
-fn get_diff(original: &str, output: &str) -> String {
-    let s1 = easy_counter_diff(original);
-    let s2 = easy_counter_diff(output);
-
-    let changeset = Changeset::new(&s1, &s2, "");
-    let mut ret_text = String::new();
-    for (_i, x) in changeset.diffs.iter().enumerate() {
-        if let Difference::Same(text) = x {
-            let mut tmp_vec: Vec<&str> = text.split("\n").collect();
-            if let Some(sentence_count) = tmp_vec.pop() {
-                ret_text += format!("{}", sentence_count).as_str();
+    let mut ledger = Ledger::open(matches.value_of("ledger").unwrap())?;
+    let since = match matches.value_of("since") {
+        Some(date) => Some(
+            ledger
+                .timestamp(date)?
+                .with_context(|| format!("not a date: {}", date))?,
+        ),
+        None if matches.is_present("all") => Some(i64::MIN),
+        None => None,
+    };
+    let mined = v.len();
+    let mut v = ledger.filter_new(v, since)?;
+    info!(
+        "exporting {} of {} mined cards, the others are in the ledger",
+        v.len(),
+        mined
+    );
+
+    if let Some(media_dir) = matches.value_of("media-dir") {
+        let cutter = MediaCutter {
+            video: matches.value_of("video").map(String::from),
+            output_dir: media_dir.to_string(),
+            padding: matches.value_of_t("padding")?,
+        };
+        cutter.fill(&mut v)?;
+    }
+
+    if let Some(url) = matches.value_of("anki-connect") {
+        let client = AnkiConnect {
+            url: url.to_string(),
+            deck: matches.value_of("deck").unwrap().to_string(),
+            note_type: matches.value_of("note-type").unwrap().to_string(),
+            fields: match matches.value_of("fields") {
+                Some(spec) => parse_field_map(spec)?,
+                None => default_field_map(),
+            },
+            media_dir: matches.value_of("media-dir").map(String::from),
+        };
+        let notes = make_mining_notes(&v, matches.value_of("freq-db"))?;
+        let outcomes = client.push(&notes)?;
+        let (mut added, mut duplicates, mut failed) = (0, 0, 0);
+        for (export, outcome) in v.iter().zip(&outcomes) {
+            let word = &export.word.dictionary_form;
+            match outcome {
+                Outcome::Added(id) => {
+                    added += 1;
+                    info!("added {} as note {}", word, id);
+                }
+                Outcome::Duplicate => {
+                    duplicates += 1;
+                    info!("skipped {}, anki already has it", word);
+                }
+                Outcome::Failed(reason) => {
+                    failed += 1;
+                    warn!("could not add {}: {}", word, reason);
+                }
             }
-        } else if let Difference::Rem(text) = x {
-            ret_text = format!("{}{}", ret_text, text.red());
-        } else if let Difference::Add(text) = x {
-            ret_text = format!("{}{}\n", ret_text, text.green());
         }
+        info!(
+            "{} added, {} duplicates skipped, {} failed",
+            added, duplicates, failed
+        );
+        // failed notes are left out of the ledger, so the next run sends them again
+        let sent: Vec<AnkiExport> = v
+            .into_iter()
+            .zip(&outcomes)
+            .filter(|(_, outcome)| !matches!(outcome, Outcome::Failed(_)))
+            .map(|(export, _)| export)
+            .collect();
+        ledger.record(&sent, source)?;
+        return Ok(());
+    }
+    if let Some(apkg) = matches.value_of("apkg") {
+        let notes = make_mining_notes(&v, matches.value_of("freq-db"))?;
+        write_apkg(
+            apkg,
+            matches.value_of("deck").unwrap(),
+            &notes,
+            matches.value_of("media-dir"),
+        )?;
+        info!("wrote {} notes to {}", notes.len(), apkg);
+        ledger.record(&v, source)?;
+        return Ok(());
     }
-    ret_text
+    let data = make_mining_csv(&v, matches.value_of("freq-db"))?;
+    write_output(&data, matches.value_of("output"))?;
+    ledger.record(&v, source)?;
+    Ok(())
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct MiningCard<'a> {
-    vocab_kanji: &'a str,
-    vocab_kanji_migaku: &'a str,
-    vocab_furigana: &'a str,
-    vocab_kana: &'a str,
-    vocab_def_en: Option<&'a str>,
-    vocab_def_ja: Option<&'a str>,
-    vocab_audio: Option<&'a str>,
-    vocab_pos: &'a str,
-    vocab_pos_info: &'a str,
-    pitch_accent: Option<u8>,
-    picture: Option<&'a str>,
-    sentence: &'a str,
-    sentence_migaku: &'a str,
-    sentence_furigana: &'a str,
-    sentence_def: Option<&'a str>,
-    sentence_audio: Option<&'a str>,
-    hint: Option<&'a str>,
-    extra_info: Option<&'a str>,
-    kanjified: Option<&'a str>,
-    freq_2016_ja: Option<u32>,
-    freq_narou: Option<u32>,
-    freq_anime_jdrama: Option<u32>,
-    freq_netflix: Option<u32>,
+fn build_db(matches: &ArgMatches) -> Result<()> {
+    let db_file_output = matches.value_of("output").unwrap();
+    if let Some(narou) = matches.value_of("narou") {
+        make_narou_db(narou, db_file_output)?;
+    }
+    if let Some(netflix) = matches.value_of("netflix") {
+        make_freq_db(netflix, db_file_output, "netflix")?;
+    }
+    if let Some(anime_jdrama) = matches.value_of("anime-jdrama") {
+        make_freq_db(anime_jdrama, db_file_output, "anime_jdrama")?;
+    }
+    if let Some(freq_2016) = matches.value_of("freq-2016") {
+        make_freq_2016_ja(freq_2016, db_file_output)?;
+    }
+    if let Some(wadoku) = matches.value_of("wadoku") {
+        parse_wadoku_xml(wadoku)?;
+    }
+    Ok(())
 }
 
-fn main() {
-    //let matches = App::new(PROGRAM_NAME)
-    //    .setting(AppSettings::DisableHelpSubcommand)
-    //    .version(crate_version!())
-    //    .author(crate_authors!())
-    //    .about(crate_description!())
-    //    .arg(
-    //        Arg::new("input")
-    //            .about("the xml output file from goldendict")
-    //            .required(true),
-    //    )
-    //    .get_matches();
-
-    //if let Some(xml_file) = matches.value_of("input") {
-    //    parse_gdict_xml_output(xml_file);
-    //    Command::new("jumanpp")
-    //        .arg("sentences.txt")
-    //        .arg("-o")
-    //        .arg("jumanpp.txt")
-    //        .spawn()
-    //        .expect("jumanpp command failed to start");
-    //    let v: Vec<AnkiExport> = parse_jumanpp_output("jumanpp.txt", "vocabs.txt");
-    //    let text = get_diff("vocabs.txt", "pad.txt");
-    //} else {
-    //    xkcd_unreachable!();
-    //}
-    let v: Vec<AnkiExport> =
-        parse_jumanpp_output("jumanpp.txt", "vocabs.txt", "debug.txt", "result.txt");
-
-    let mut wtr = WriterBuilder::new()
-        .delimiter(b';')
-        .has_headers(false)
-        .from_writer(vec![]);
-    for i in v {
-        let re_bold = Regex::new(format!("(?P<kanji>{})", &i.word.original).as_str()).unwrap();
-        let re_bold_furigana =
-            Regex::new(format!("(?P<kanji>{})", regex::escape(&i.word.reading_furigana)).as_str())
-                .unwrap();
-        let bold_sentence = re_bold.replace_all(&i.sentence, "<b>$kanji</b>");
-        let bold_sentence_furigana =
-            re_bold_furigana.replace_all(&i.sentence_furigana, "<b>$kanji</b>");
-        wtr.serialize(MiningCard {
-            vocab_kanji: &i.word.dictionary_form,
-            vocab_kanji_migaku: &i.word.dictionary_form,
-            vocab_furigana: &i.word.reading_furigana,
-            vocab_kana: &i.word.reading_kana,
-            vocab_def_en: None,
-            vocab_def_ja: None,
-            vocab_audio: None,
-            vocab_pos: &i.word.pos,
-            vocab_pos_info: &i.word.pos_information,
-            pitch_accent: None,
-            picture: None,
-            sentence: &bold_sentence,
-            sentence_migaku: &bold_sentence,
-            sentence_furigana: &bold_sentence_furigana,
-            sentence_def: None,
-            sentence_audio: None,
-            hint: None,
-            extra_info: None,
-            kanjified: None,
-            freq_2016_ja: None,
-            freq_narou: None,
-            freq_anime_jdrama: None,
-            freq_netflix: None,
-        })
-        .expect("could not serialize");
+fn main() -> Result<()> {
+    setup_logging()?;
+
+    let db_source_arg = |name: &'static str, help: &'static str| {
+        Arg::new(name)
+            .long(name)
+            .takes_value(true)
+            .value_name("FILE")
+            .help(help)
+    };
+
+    let matches = Command::new(PROGRAM_NAME)
+        .disable_help_subcommand(true)
+        .subcommand_required(true)
+        .arg_required_else_help(true)
+        .version(crate_version!())
+        .author(crate_authors!())
+        .about(crate_description!())
+        .subcommand(
+            Command::new("extract")
+                .about("split a goldendict export into sentences and looked up words")
+                .args(input_args())
+                .arg(vocabs_arg())
+                .arg(sentences_arg())
+                .args(pairing_args())
+                .args(subtitle_args())
+                .args(mokuro_args())
+                .args(ruby_args())
+                .args(epub_args())
+                .arg(log_arg()),
+        )
+        .subcommand(
+            Command::new("analyze")
+                .about("run the analyzer over the extracted sentences")
+                .arg(sentences_arg())
+                .args(analyzer_args()),
+        )
+        .subcommand(
+            Command::new("export")
+                .about("match the analyzed sentences against the vocabs and print the anki csv")
+                .args(analyzer_args())
+                .arg(vocabs_arg())
+                .args(export_args())
+                .args(deck_args())
+                .args(media_args())
+                .arg(
+                    Arg::new("source")
+                        .long("source")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .required(true)
+                        .help("where the lookups came from, usually the input of extract, recorded in the ledger"),
+                )
+                .arg(
+                    Arg::new("cues")
+                        .long("cues")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .help("the subtitle timings written by extract --subtitles"),
+                )
+                .arg(
+                    Arg::new("regions")
+                        .long("regions")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .help("the manga text boxes written by extract --mokuro"),
+                )
+                .arg(
+                    Arg::new("readings")
+                        .long("readings")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .help("the ruby readings written by extract --ruby or --epub"),
+                )
+                .arg(
+                    Arg::new("locations")
+                        .long("locations")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .help("the books written by extract --epub or for a kindle vocab.db"),
+                ),
+        )
+        .subcommand(
+            Command::new("run")
+                .about("extract, analyze and export in one go")
+                .args(input_args())
+                .arg(vocabs_arg())
+                .arg(sentences_arg())
+                .args(pairing_args())
+                .args(subtitle_args())
+                .args(mokuro_args())
+                .args(ruby_args())
+                .args(epub_args())
+                .arg(log_arg())
+                .args(analyzer_args())
+                .args(export_args())
+                .args(deck_args())
+                .args(media_args()),
+        )
+        .subcommand(
+            Command::new("diff")
+                .about("compare the per sentence word counts of two numbered word lists")
+                .arg(
+                    Arg::new("original")
+                        .help("the expected list, usually vocabs.txt")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("output")
+                        .help("the list to check, usually result.txt")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("build-db")
+                .about("import frequency lists into the sqlite database")
+                .arg(path_arg(
+                    "output",
+                    "database/freq.db",
+                    "the database to create the tables in",
+                ))
+                .arg(db_source_arg(
+                    "narou",
+                    "narou frequency list (yomichan json)",
+                ))
+                .arg(db_source_arg(
+                    "netflix",
+                    "netflix frequency list (yomichan json)",
+                ))
+                .arg(db_source_arg(
+                    "anime-jdrama",
+                    "anime & jdrama frequency list (yomichan json)",
+                ))
+                .arg(db_source_arg(
+                    "freq-2016",
+                    "2016 japanese frequency list (';' delimited csv)",
+                ))
+                .arg(db_source_arg(
+                    "wadoku",
+                    "wadoku xml dump, printed for inspection",
+                )),
+        )
+        .get_matches();
+
+    match matches.subcommand() {
+        Some(("extract", sub)) => extract(sub)?,
+        Some(("analyze", sub)) => analyze(sub)?,
+        Some(("export", sub)) => export(
+            sub,
+            sub.value_of("source").unwrap(),
+            sub.value_of("cues"),
+            sub.value_of("regions"),
+            sub.value_of("readings"),
+            sub.value_of("locations"),
+        )?,
+        Some(("run", sub)) => {
+            extract(sub)?;
+            analyze(sub)?;
+            let subtitles = sub.value_of("subtitles");
+            let mokuro = sub.value_of("mokuro");
+            let ruby = sub.value_of("ruby");
+            let epub = sub.value_of("epub");
+            let log = sub.value_of("log");
+            let (input, format) = input_of(sub)?;
+            let kindle = format == InputFormat::Kindle
+                && [subtitles, mokuro, ruby, epub, log]
+                    .iter()
+                    .all(Option::is_none);
+            export(
+                sub,
+                &input,
+                subtitles.and(sub.value_of("cues")),
+                mokuro.and(sub.value_of("regions")),
+                ruby.or(epub).and(sub.value_of("readings")),
+                if epub.is_some() || kindle {
+                    sub.value_of("locations")
+                } else {
+                    None
+                },
+            )?;
+        }
+        Some(("diff", sub)) => println!(
+            "{}",
+            get_diff(
+                sub.value_of("original").unwrap(),
+                sub.value_of("output").unwrap()
+            )?
+        ),
+        Some(("build-db", sub)) => build_db(sub)?,
+        _ => xkcd_unreachable!(),
     }
-    let data = String::from_utf8(wtr.into_inner().expect("could not wrap into inner"))
-        .expect("could not convert to utf8");
-    println!("{}", data);
+    Ok(())
 }
@@ -1,13 +1,23 @@
-use csv::{ReaderBuilder, WriterBuilder};
+use csv::ReaderBuilder;
+use log::warn;
+use quick_xml::events::Event;
+use quick_xml::{Reader, Writer};
 use regex::Regex;
 use rusqlite::{params, Connection};
 use serde::{Deserialize, Serialize};
 use std::fs;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
 
-fn make_narou_db(file_path: &str, db_file_output: &str) {
-    let s = fs::read_to_string(file_path).unwrap();
-    let mut conn = Connection::open(db_file_output).expect("could not open database file");
-    let tx = conn.transaction().expect("could not open transaction");
+use crate::error::{Error, Result};
+
+/// Imports the narou frequency list (a yomichan json) into the `narou` table.
+pub fn make_narou_db(file_path: &str, db_file_output: &str) -> Result<()> {
+    let s = fs::read_to_string(file_path).map_err(Error::io(file_path))?;
+    let mut conn = Connection::open(db_file_output).map_err(Error::database(db_file_output))?;
+    let tx = conn
+        .transaction()
+        .map_err(Error::database(db_file_output))?;
     //schema
     tx.execute(
         "CREATE TABLE narou (
@@ -18,7 +28,7 @@ fn make_narou_db(file_path: &str, db_file_output: &str) {
             )",
         rusqlite::NO_PARAMS,
     )
-    .expect("could not import schema");
+    .map_err(Error::database(db_file_output))?;
 
     let re =
         Regex::new(r#"\["(.*?)",\s?"freq",\s?\{"reading":\s?"(.*?)",\s?"frequency":\s(\d+)\}\]"#)
@@ -28,23 +38,28 @@ fn make_narou_db(file_path: &str, db_file_output: &str) {
             "INSERT INTO narou (word, reading, freq) VALUES (?1, ?2, ?3)",
             params![&cap[1], &cap[2], &cap[3]],
         )
-        .expect("could not insert query");
+        .map_err(Error::database(db_file_output))?;
     }
 
     tx.execute(
         "CREATE INDEX ix_word_narou ON narou (word COLLATE NOCASE)",
         rusqlite::NO_PARAMS,
     )
-    .expect("could not create index");
-    tx.commit().unwrap();
+    .map_err(Error::database(db_file_output))?;
+    tx.commit().map_err(Error::database(db_file_output))?;
+
+    Ok(())
 }
 
+/// Imports a word-only frequency list (a yomichan json) into `db_name`.
 // this ONLY works for anime&jdrama and netflix json
 // aka db_name should only takes netflix and anime_jdrama
-fn make_freq_db(file_path: &str, db_file_output: &str, db_name: &str) {
-    let s = fs::read_to_string(file_path).unwrap();
-    let mut conn = Connection::open(db_file_output).expect("could not open database file");
-    let tx = conn.transaction().expect("could not open transaction");
+pub fn make_freq_db(file_path: &str, db_file_output: &str, db_name: &str) -> Result<()> {
+    let s = fs::read_to_string(file_path).map_err(Error::io(file_path))?;
+    let mut conn = Connection::open(db_file_output).map_err(Error::database(db_file_output))?;
+    let tx = conn
+        .transaction()
+        .map_err(Error::database(db_file_output))?;
     //schema
     tx.execute(
         format!(
@@ -58,7 +73,7 @@ fn make_freq_db(file_path: &str, db_file_output: &str, db_name: &str) {
         .as_str(),
         rusqlite::NO_PARAMS,
     )
-    .expect("could not import schema");
+    .map_err(Error::database(db_file_output))?;
 
     let re = Regex::new(r#"\["(.*?)",\s?"freq",\s?(\d+)\]"#).unwrap();
     for cap in re.captures_iter(&s) {
@@ -66,7 +81,7 @@ fn make_freq_db(file_path: &str, db_file_output: &str, db_name: &str) {
             format!("INSERT INTO {} (word, freq) VALUES (?1, ?2)", db_name).as_str(),
             params![&cap[1], &cap[2]],
         )
-        .expect("could not insert query");
+        .map_err(Error::database(db_file_output))?;
     }
 
     tx.execute(
@@ -77,8 +92,10 @@ fn make_freq_db(file_path: &str, db_file_output: &str, db_name: &str) {
         .as_str(),
         rusqlite::NO_PARAMS,
     )
-    .expect("could not create index");
-    tx.commit().unwrap();
+    .map_err(Error::database(db_file_output))?;
+    tx.commit().map_err(Error::database(db_file_output))?;
+
+    Ok(())
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -87,18 +104,21 @@ struct Freq2016 {
     data: String,
 }
 
-fn make_freq_2016_ja(file_path: &str, db_file_output: &str) {
-    let s = fs::read_to_string(file_path).unwrap();
+/// Imports the 2016 Japanese frequency list into the `freq2016` table.
+pub fn make_freq_2016_ja(file_path: &str, db_file_output: &str) -> Result<()> {
+    let s = fs::read_to_string(file_path).map_err(Error::io(file_path))?;
     let mut rdr = ReaderBuilder::new()
         .delimiter(b';')
         .has_headers(false)
         .from_reader(s.as_bytes());
-    let mut iter = rdr.deserialize();
+    let iter = rdr.deserialize();
     let re = Regex::new(r#"<div class="ce_js">(\d+)"#).unwrap();
     let re_occ = Regex::new(r#"<BR>(\d+) of 13,280,660"#).unwrap();
 
-    let mut conn = Connection::open(db_file_output).expect("could not open database file");
-    let tx = conn.transaction().expect("could not open transaction");
+    let mut conn = Connection::open(db_file_output).map_err(Error::database(db_file_output))?;
+    let tx = conn
+        .transaction()
+        .map_err(Error::database(db_file_output))?;
     //schema
     tx.execute(
         "CREATE TABLE freq2016 (
@@ -109,67 +129,185 @@ fn make_freq_2016_ja(file_path: &str, db_file_output: &str) {
             )",
         rusqlite::NO_PARAMS,
     )
-    .expect("could not import schema");
-
-    for result in iter {
-        let record: Freq2016 = result.expect("could not get result");
-        let cap = re.captures(&record.data).unwrap();
-        let cap_occ = re_occ.captures(&record.data).unwrap();
+    .map_err(Error::database(db_file_output))?;
 
-        let freq_cap = cap.get(1).unwrap().as_str().parse::<u32>().unwrap();
-        let occ_cap = cap_occ.get(1).unwrap().as_str().parse::<u32>().unwrap();
+    for (line, result) in iter.enumerate() {
+        let record: Freq2016 = match result {
+            Ok(record) => record,
+            Err(e) => {
+                warn!(
+                    "skipping {}",
+                    Error::line(file_path, line + 1, "", &e.to_string())
+                );
+                continue;
+            }
+        };
+        let caps = re
+            .captures(&record.data)
+            .zip(re_occ.captures(&record.data))
+            .and_then(|(cap, cap_occ)| {
+                Some((cap[1].parse::<u32>().ok()?, cap_occ[1].parse::<u32>().ok()?))
+            });
+        let (freq_cap, occ_cap) = match caps {
+            Some(caps) => caps,
+            None => {
+                warn!(
+                    "skipping {}",
+                    Error::line(file_path, line + 1, &record.word, "no frequency found")
+                );
+                continue;
+            }
+        };
 
         tx.execute(
             "INSERT INTO freq2016 (word, freq, occ) VALUES (?1, ?2, ?3)",
             params![&record.word, freq_cap, occ_cap],
         )
-        .expect("could not insert query");
+        .map_err(Error::database(db_file_output))?;
     }
 
     tx.execute(
         "CREATE INDEX ix_word_ja2016 ON freq2016 (word COLLATE NOCASE)",
         rusqlite::NO_PARAMS,
     )
-    .expect("could not create index");
-    tx.commit().unwrap();
-}
+    .map_err(Error::database(db_file_output))?;
+    tx.commit().map_err(Error::database(db_file_output))?;
 
-#[derive(Debug, Deserialize)]
-struct Wadoku {
-    date: String,
-    entry: Vec<WadokuEntry>,
+    Ok(())
 }
 
 #[derive(Debug, Deserialize)]
-struct WadokuEntry {
-    id: u32,
-    form: WadokuEntryForm,
+pub struct WadokuEntry {
+    pub id: u32,
+    pub form: WadokuEntryForm,
 }
 
 #[derive(Debug, Deserialize)]
-struct WadokuEntryForm {
+pub struct WadokuEntryForm {
     #[serde(rename = "orth")]
-    orths: Vec<WadokuOrth>,
-    reading: WadokuEntryReading,
+    pub orths: Vec<WadokuOrth>,
+    pub reading: WadokuEntryReading,
 }
 
 #[derive(Debug, Deserialize)]
-struct WadokuOrth {
-    midashigo: Option<String>,
+pub struct WadokuOrth {
+    pub midashigo: Option<String>,
     #[serde(rename = "$value")]
-    orth_value: String,
+    pub orth_value: String,
 }
 
 #[derive(Debug, Deserialize)]
-struct WadokuEntryReading {
+pub struct WadokuEntryReading {
     #[serde(rename = "hira")]
-    hiragana_reading: String,
+    pub hiragana_reading: String,
     #[serde(rename = "accent")]
-    accents: Option<Vec<u8>>,
+    pub accents: Option<Vec<u8>>,
+}
+
+/// The `<entry>`s of a Wadoku xml dump, read one at a time so that only a
+/// single entry is ever held in memory.
+pub struct WadokuEntries<R: BufRead> {
+    reader: Reader<R>,
+    buf: Vec<u8>,
+    path: String,
+    done: bool,
+}
+
+impl WadokuEntries<BufReader<File>> {
+    /// Opens the dump at `path`.
+    pub fn open(path: &str) -> Result<Self> {
+        let file = File::open(path).map_err(Error::io(path))?;
+        Ok(WadokuEntries::from_reader(BufReader::new(file), path))
+    }
 }
 
-pub fn parse_wadoku_xml(file_path: &str) {
-    let wadoku_xml = fs::read_to_string(file_path).unwrap();
-    let wadoku: Wadoku = quick_xml::de::from_str(&wadoku_xml).expect("could not parse xml");
-    println!("{:#?}", wadoku);
+impl<R: BufRead> WadokuEntries<R> {
+    /// Reads a dump from `reader`, `path` naming it in errors.
+    pub fn from_reader(reader: R, path: &str) -> Self {
+        let mut reader = Reader::from_reader(reader);
+        reader
+            .expand_empty_elements(true)
+            .check_end_names(true)
+            .trim_text(true);
+        WadokuEntries {
+            reader,
+            buf: Vec::new(),
+            path: path.to_string(),
+            done: false,
+        }
+    }
+
+    fn next_entry(&mut self) -> Result<Option<WadokuEntry>> {
+        // the events of the current entry, written back out to be deserialized
+        let mut entry = Writer::new(Vec::new());
+        let mut depth = 0;
+        loop {
+            self.buf.clear();
+            let event = self
+                .reader
+                .read_event(&mut self.buf)
+                .map_err(Error::xml(&self.path))?;
+            match &event {
+                Event::Start(e) if depth == 0 && e.local_name() != b"entry" => continue,
+                Event::Start(_) => depth += 1,
+                Event::End(_) if depth == 0 => continue,
+                Event::End(_) => depth -= 1,
+                Event::Eof => return Ok(None),
+                _ if depth == 0 => continue,
+                _ => {}
+            }
+            entry.write_event(event).map_err(Error::xml(&self.path))?;
+            if depth == 0 {
+                let entry = entry.into_inner();
+                return quick_xml::de::from_reader(entry.as_slice())
+                    .map(Some)
+                    .map_err(Error::xml(&self.path));
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for WadokuEntries<R> {
+    type Item = Result<WadokuEntry>;
+
+    fn next(&mut self) -> Option<Result<WadokuEntry>> {
+        if self.done {
+            return None;
+        }
+        let entry = self.next_entry();
+        self.done = !matches!(entry, Ok(Some(_)));
+        entry.transpose()
+    }
+}
+
+/// Parses a Wadoku xml dump and prints it for inspection, one entry at a time.
+pub fn parse_wadoku_xml(file_path: &str) -> Result<()> {
+    for entry in WadokuEntries::open(file_path)? {
+        println!("{:#?}", entry?);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wadoku_entries() {
+        let xml = r#"<entries xmlns="http://www.wadoku.de/xml/entry">
+            <entry id="1"><form><orth midashigo="true">尋ねる</orth><orth>訊ねる</orth><reading><hira>たずねる</hira><accent>3</accent></reading></form></entry>
+            <entry id="2"><form><orth>友達</orth><reading><hira>ともだち</hira></reading></form></entry>
+        </entries>"#;
+        let entries: Vec<WadokuEntry> = WadokuEntries::from_reader(xml.as_bytes(), "wadoku.xml")
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].id, 1);
+        assert_eq!(entries[0].form.orths.len(), 2);
+        assert_eq!(entries[0].form.orths[1].orth_value, "訊ねる");
+        assert_eq!(entries[0].form.reading.hiragana_reading, "たずねる");
+        assert_eq!(entries[0].form.reading.accents, Some(vec![3]));
+        assert_eq!(entries[1].form.reading.accents, None);
+    }
 }
@@ -0,0 +1,163 @@
+use log::warn;
+use std::fs;
+use std::process;
+
+use crate::analyzer::{Analyzer, Morpheme};
+use crate::error::{Error, Result};
+use crate::utils::katakana_to_hiragana;
+
+/// The dictionary MeCab ran with, which decides the layout of the features.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MecabDictionary {
+    /// `品詞,品詞細分類1,品詞細分類2,品詞細分類3,活用型,活用形,原形,読み,発音`
+    Ipadic,
+    /// `pos1,pos2,pos3,pos4,cType,cForm,lForm,lemma,orth,pron,orthBase,...,kana,...`
+    Unidic,
+}
+
+/// MeCab, run through the `mecab` binary on `PATH` with its default output.
+#[derive(Debug)]
+pub struct Mecab {
+    pub dictionary: MecabDictionary,
+    pub dicdir: Option<String>,
+}
+
+impl Analyzer for Mecab {
+    fn run(&self, sentences_path: &str, output_path: &str) -> Result<()> {
+        let mut command = process::Command::new("mecab");
+        if let Some(dicdir) = &self.dicdir {
+            command.arg("-d").arg(dicdir);
+        }
+        let status = command
+            .arg(sentences_path)
+            .arg("-o")
+            .arg(output_path)
+            .status()
+            .map_err(Error::io("mecab"))?;
+        if !status.success() {
+            return Err(Error::Command {
+                program: "mecab".to_string(),
+                status: status.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    fn parse(&self, output_path: &str) -> Result<Vec<Vec<Morpheme>>> {
+        parse_mecab_output(output_path, self.dictionary)
+    }
+}
+
+fn feature<'a>(features: &[&'a str], i: usize) -> Option<&'a str> {
+    features
+        .get(i)
+        .copied()
+        .filter(|f| !f.is_empty() && *f != "*")
+}
+
+/// Parses one `surface\tfeatures` line of the MeCab output.
+pub fn parse_mecab_line(line: &str, dictionary: MecabDictionary) -> Option<Morpheme> {
+    let mut split = line.splitn(2, '\t');
+    let surface = split.next()?;
+    let features: Vec<&str> = split.next()?.split(',').collect();
+    if surface.is_empty() || features.len() < 6 {
+        return None;
+    }
+
+    // unknown words come without lemma and reading
+    let (lemma, reading) = match dictionary {
+        MecabDictionary::Ipadic => (feature(&features, 6), feature(&features, 7)),
+        MecabDictionary::Unidic => (
+            feature(&features, 10),
+            feature(&features, 20).or_else(|| feature(&features, 9)),
+        ),
+    };
+
+    Some(Morpheme {
+        surface: surface.to_string(),
+        reading: katakana_to_hiragana(reading.unwrap_or(surface)),
+        lemma: lemma.unwrap_or(surface).to_string(),
+        pos: features[0].to_string(),
+        sub_pos: features[1].to_string(),
+        conjugation_type: features[4].to_string(),
+        conjugation_form: features[5].to_string(),
+        semantic_info: "*".to_string(),
+        normalized_form: None,
+        canonical_form: None,
+        alternatives: Vec::new(),
+    })
+}
+
+/// Reads the MeCab output at `output_path`, one `Vec` per `EOS`.
+pub fn parse_mecab_output(
+    output_path: &str,
+    dictionary: MecabDictionary,
+) -> Result<Vec<Vec<Morpheme>>> {
+    let mecab_output = fs::read_to_string(output_path).map_err(Error::io(output_path))?;
+
+    let mut sentences = Vec::new();
+    let mut sentence = Vec::new();
+
+    for (line, x) in mecab_output.lines().enumerate() {
+        if x == "EOS" {
+            sentences.push(sentence);
+            sentence = Vec::new();
+        } else {
+            match parse_mecab_line(x, dictionary) {
+                Some(morpheme) => sentence.push(morpheme),
+                None => warn!(
+                    "skipping {}",
+                    Error::line(output_path, line + 1, x, "not a morpheme line")
+                ),
+            }
+        }
+    }
+
+    Ok(sentences)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ipadic_line() {
+        let m = parse_mecab_line(
+            "尋ね\t動詞,自立,*,*,一段,連用形,尋ねる,タズネ,タズネ",
+            MecabDictionary::Ipadic,
+        )
+        .unwrap();
+        assert_eq!(m.surface, "尋ね");
+        assert_eq!(m.reading, "たずね");
+        assert_eq!(m.lemma, "尋ねる");
+        assert_eq!(m.pos, "動詞");
+        assert_eq!(m.sub_pos, "自立");
+        assert_eq!(m.conjugation_type, "一段");
+        assert_eq!(m.conjugation_form, "連用形");
+    }
+
+    #[test]
+    fn test_parse_ipadic_unknown_word() {
+        let m = parse_mecab_line("ググる\t名詞,一般,*,*,*,*,*", MecabDictionary::Ipadic).unwrap();
+        assert_eq!(m.reading, "ぐぐる");
+        assert_eq!(m.lemma, "ググる");
+    }
+
+    #[test]
+    fn test_parse_unidic_line() {
+        let m = parse_mecab_line(
+            "尋ね\t動詞,一般,*,*,下一段-ナ行,連用形-一般,タズネル,尋ねる,尋ね,タズネ,尋ねる,タズネル,和,*,*,*,*,*,*,用,タズネ,タズネル,タズネ,タズネル,3,C1,*,6253220232913537,22749",
+            MecabDictionary::Unidic,
+        )
+        .unwrap();
+        assert_eq!(m.reading, "たずね");
+        assert_eq!(m.lemma, "尋ねる");
+        assert_eq!(m.pos, "動詞");
+        assert_eq!(m.conjugation_form, "連用形-一般");
+    }
+
+    #[test]
+    fn test_parse_bad_line() {
+        assert_eq!(parse_mecab_line("EOS?", MecabDictionary::Ipadic), None);
+    }
+}
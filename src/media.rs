@@ -0,0 +1,273 @@
+use log::warn;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use crate::anki::AnkiExport;
+use crate::error::{Error, Result};
+use crate::mokuro::Region;
+use crate::subtitle::Cue;
+
+/// The extensions tried when looking for the video next to a subtitle file.
+const VIDEO_EXTENSIONS: &[&str] = &["mkv", "mp4", "webm", "avi", "m4v", "mov"];
+
+/// Cuts the sentence audio and a screenshot of each cue out of a local video,
+/// or the text box of each manga page, with the `ffmpeg` binary on `PATH`.
+#[derive(Debug)]
+pub struct MediaCutter {
+    /// the video of every cue, otherwise the one next to its subtitle file
+    pub video: Option<String>,
+    /// where the media is written, usually Anki's `collection.media`
+    pub output_dir: String,
+    /// milliseconds of audio kept before and after each cue
+    pub padding: u64,
+}
+
+/// Finds the video with the same name as `subtitle_path`, e.g. `ep01.mkv` for
+/// `ep01.ja.srt` or `ep01.ass`.
+pub fn find_video(subtitle_path: &str) -> Option<PathBuf> {
+    let path = Path::new(subtitle_path);
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut stem = path.file_stem()?.to_str()?;
+    loop {
+        for extension in VIDEO_EXTENSIONS {
+            let video = dir.join(format!("{}.{}", stem, extension));
+            if video.is_file() {
+                return Some(video);
+            }
+        }
+        // drop a language suffix such as .ja
+        stem = &stem[..stem.rfind('.')?];
+    }
+}
+
+/// The stem of `path` with anything but letters, digits and `-` replaced, to
+/// be safe in a media file name.
+fn slug(path: &Path) -> String {
+    path.file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("media")
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// The file name of media cut from `video`, the same on every run so that it
+/// is only cut once. Episodes are often just numbered in a directory per
+/// season, so that directory is part of the name too.
+pub fn media_name(video: &str, start: u64, end: u64, extension: &str) -> String {
+    let video = Path::new(video);
+    let season = video.parent().map(slug).unwrap_or_default();
+    format!(
+        "gd2anki_{}_{}_{}-{}.{}",
+        season,
+        slug(video),
+        start,
+        end,
+        extension
+    )
+}
+
+/// The file name of `region` cropped out of its page. Page images are usually
+/// just numbered, so the volume directory is part of the name too.
+pub fn crop_name(region: &Region) -> String {
+    let image = Path::new(&region.image);
+    let volume = image.parent().map(slug).unwrap_or_default();
+    format!(
+        "gd2anki_{}_{}_{}-{}-{}x{}.jpg",
+        volume,
+        slug(image),
+        region.x,
+        region.y,
+        region.width,
+        region.height
+    )
+}
+
+/// Formats milliseconds as the seconds ffmpeg takes, e.g. `62.345`.
+fn seconds(ms: u64) -> String {
+    format!("{}.{:03}", ms / 1000, ms % 1000)
+}
+
+fn run_ffmpeg(args: &[&str], output: &Path) -> Result<()> {
+    let status = process::Command::new("ffmpeg")
+        .args(["-y", "-loglevel", "error"])
+        .args(args)
+        .arg(output)
+        .status()
+        .map_err(Error::io("ffmpeg"))?;
+    if !status.success() {
+        return Err(Error::Command {
+            program: "ffmpeg".to_string(),
+            status: status.to_string(),
+        });
+    }
+    Ok(())
+}
+
+impl MediaCutter {
+    fn video_of(&self, cue: &Cue) -> Option<String> {
+        match &self.video {
+            Some(video) => Some(video.clone()),
+            None => find_video(&cue.file).map(|v| v.to_string_lossy().into_owned()),
+        }
+    }
+
+    /// Cuts the padded audio of `cue` from `video` and returns its file name.
+    pub fn cut_audio(&self, video: &str, cue: &Cue) -> Result<String> {
+        let start = cue.start.saturating_sub(self.padding);
+        let end = cue.end + self.padding;
+        let name = media_name(video, start, end, "mp3");
+        let output = Path::new(&self.output_dir).join(&name);
+        if !output.is_file() {
+            run_ffmpeg(
+                &[
+                    "-ss",
+                    &seconds(start),
+                    "-t",
+                    &seconds(end - start),
+                    "-i",
+                    video,
+                    "-vn",
+                    "-map",
+                    "0:a:0",
+                    "-c:a",
+                    "libmp3lame",
+                    "-q:a",
+                    "4",
+                ],
+                &output,
+            )?;
+        }
+        Ok(name)
+    }
+
+    /// Grabs the frame at the middle of `cue` from `video` and returns its file
+    /// name.
+    pub fn cut_picture(&self, video: &str, cue: &Cue) -> Result<String> {
+        let middle = cue.start + (cue.end.saturating_sub(cue.start)) / 2;
+        let name = media_name(video, middle, middle, "jpg");
+        let output = Path::new(&self.output_dir).join(&name);
+        if !output.is_file() {
+            run_ffmpeg(
+                &[
+                    "-ss",
+                    &seconds(middle),
+                    "-i",
+                    video,
+                    "-frames:v",
+                    "1",
+                    "-q:v",
+                    "3",
+                ],
+                &output,
+            )?;
+        }
+        Ok(name)
+    }
+
+    /// Crops `region` out of its page and returns its file name.
+    pub fn crop_picture(&self, region: &Region) -> Result<String> {
+        let name = crop_name(region);
+        let output = Path::new(&self.output_dir).join(&name);
+        if !output.is_file() {
+            let crop = format!(
+                "crop={}:{}:{}:{}",
+                region.width, region.height, region.x, region.y
+            );
+            run_ffmpeg(
+                &[
+                    "-i",
+                    &region.image,
+                    "-vf",
+                    &crop,
+                    "-frames:v",
+                    "1",
+                    "-q:v",
+                    "3",
+                ],
+                &output,
+            )?;
+        }
+        Ok(name)
+    }
+
+    /// Fills the sentence audio and picture of every export with a cue, and
+    /// the picture of every export with a region. A cue without a video or
+    /// media that ffmpeg fails on is reported and left out, and so is all of
+    /// it when ffmpeg is not installed.
+    pub fn fill(&self, exports: &mut [AnkiExport]) -> Result<()> {
+        fs::create_dir_all(&self.output_dir).map_err(Error::io(&self.output_dir))?;
+
+        for export in exports.iter_mut() {
+            let media = match (&export.cue, &export.region) {
+                (Some(cue), _) => {
+                    let video = match self.video_of(cue) {
+                        Some(video) => video,
+                        None => {
+                            warn!("no video found next to {}, pass --video", cue.file);
+                            continue;
+                        }
+                    };
+                    self.cut_audio(&video, cue)
+                        .and_then(|audio| Ok((Some(audio), self.cut_picture(&video, cue)?)))
+                }
+                (None, Some(region)) => self.crop_picture(region).map(|picture| (None, picture)),
+                (None, None) => continue,
+            };
+            match media {
+                Ok((audio, picture)) => {
+                    export.sentence_audio = audio;
+                    export.picture = Some(picture);
+                }
+                Err(e @ Error::Command { .. }) => {
+                    warn!("no media for {}: {}", export.word.original, e)
+                }
+                Err(Error::Io { path, source })
+                    if path == "ffmpeg" && source.kind() == io::ErrorKind::NotFound =>
+                {
+                    warn!("ffmpeg is not installed, the cards will have no media");
+                    return Ok(());
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_media_name() {
+        assert_eq!(
+            media_name("/videos/Show S01E01 [1080p].mkv", 61000, 63500, "mp3"),
+            "gd2anki_videos_Show_S01E01__1080p__61000-63500.mp3"
+        );
+        assert_ne!(
+            media_name("Show/S1/01.mkv", 61000, 63500, "mp3"),
+            media_name("Show/S2/01.mkv", 61000, 63500, "mp3")
+        );
+        assert_eq!(
+            crop_name(&Region {
+                image: "manga/vol 1/001.jpg".to_string(),
+                x: 800,
+                y: 100,
+                width: 100,
+                height: 300,
+            }),
+            "gd2anki_vol_1_001_800-100-100x300.jpg"
+        );
+        assert_eq!(seconds(62345), "62.345");
+        assert_eq!(seconds(5), "0.005");
+    }
+}
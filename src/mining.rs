@@ -0,0 +1,348 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::ops::Range;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::analyzer::Morpheme;
+use crate::anki::{AnkiExport, WordInformation};
+use crate::error::{Error, Result};
+use crate::furigana::{get_canonical_reading, get_furigana_reading, get_reading_stem};
+use crate::utils::katakana_to_hiragana;
+
+/// Matches the morphemes of each analyzed sentence against the numbered word
+/// list and returns one export per looked up word found in its sentence.
+///
+/// Every token tried is traced to `debug_path` and every match to
+/// `result_counter_path`, which `get_diff` can compare with the word list.
+pub fn mine_sentences(
+    sentences: &[Vec<Morpheme>],
+    word_list: &str,
+    debug_path: &str,
+    result_counter_path: &str,
+) -> Result<Vec<AnkiExport>> {
+    let words_string = fs::read_to_string(word_list).map_err(Error::io(word_list))?;
+    let word_index = index_word_list(&words_string);
+
+    let mut dedupe_set = HashSet::new();
+
+    let mut ret: Vec<AnkiExport> = Vec::new();
+    let mut debug_text = String::new();
+    let mut result_counter_text = String::new();
+
+    for (current_sentence_count, morphemes) in sentences.iter().enumerate() {
+        let mut sentence = String::new();
+        let mut sentence_furigana = String::new();
+        let mut saved_words_information: Vec<WordInformation> = Vec::new();
+        let looked_up_words: &[String] = word_index
+            .get(&current_sentence_count)
+            .map_or(&[], Vec::as_slice);
+        let mut unmatched_words = looked_up_words.to_vec();
+
+        for m in morphemes {
+            // Juman++ may list other analyses of the same surface, the looked
+            // up word can be any of them
+            for candidate in std::iter::once(m).chain(&m.alternatives) {
+                let dictionary_form_sanitized = &sanitized_lemma(candidate);
+                let surface = &normalize(&candidate.surface);
+                let lemma = &normalize(dictionary_form_sanitized);
+
+                debug_text += format!(
+                    "current sentence_count: {:04}, surface: {}, lemma: {}, looked up: {:?}\n",
+                    current_sentence_count,
+                    candidate.surface,
+                    dictionary_form_sanitized,
+                    looked_up_words
+                )
+                .as_str();
+
+                if !looked_up_words.iter().any(|w| w == surface || w == lemma)
+                    || dedupe_set.contains(&candidate.surface)
+                {
+                    continue;
+                }
+                debug_text += format!(
+                    "GOT THROUGH => sentence_count: {:04}, surface: {}\n",
+                    current_sentence_count, candidate.surface
+                )
+                .as_str();
+                result_counter_text +=
+                    format!("{:04} {}\n", current_sentence_count, candidate.lemma).as_str();
+                dedupe_set.insert(candidate.surface.clone());
+                unmatched_words.retain(|w| w != surface && w != lemma);
+
+                let p = match &candidate.canonical_form {
+                    Some(c) => get_canonical_reading(&c.kanji, &c.reading, &candidate.pos),
+                    None => get_reading_stem(
+                        &candidate.surface,
+                        &candidate.reading,
+                        &candidate.lemma,
+                        &candidate.pos,
+                    ),
+                };
+                saved_words_information.push(WordInformation {
+                    original: candidate.surface.clone(),
+                    dictionary_form: p.word,
+                    reading_kana: p.kana,
+                    reading_furigana: p.furigana,
+                    pos: candidate.pos.clone(),
+                    pos_information: candidate.sub_pos.clone(),
+                    normalized_form: candidate.normalized_form.clone(),
+                });
+                break;
+            }
+
+            let kanji_count = m
+                .surface
+                .chars()
+                .filter(kanji::is_kanji)
+                .collect::<Vec<char>>()
+                .len();
+
+            sentence += &m.surface;
+
+            if kanji_count > 0 {
+                sentence_furigana += &get_furigana_reading(&m.surface, &m.reading, false);
+            } else {
+                sentence_furigana += &m.surface;
+            }
+        }
+
+        // words split into several morphemes, e.g. 気 が つい for 気がつく
+        for word in unmatched_words {
+            let span = match find_span(morphemes, &word) {
+                Some(span) => &morphemes[span],
+                None => continue,
+            };
+            let information = span_information(span);
+            if dedupe_set.contains(&information.original) {
+                continue;
+            }
+            debug_text += format!(
+                "GOT THROUGH SPAN => sentence_count: {:04}, surface: {}, word: {}\n",
+                current_sentence_count, information.original, word
+            )
+            .as_str();
+            result_counter_text += format!("{:04} {}\n", current_sentence_count, word).as_str();
+            dedupe_set.insert(information.original.clone());
+            saved_words_information.push(information);
+        }
+
+        for word in saved_words_information {
+            ret.push(AnkiExport {
+                word,
+                sentence: sentence.clone(),
+                sentence_furigana: sentence_furigana.clone(),
+                sentence_number: current_sentence_count,
+                cue: None,
+                region: None,
+                location: None,
+                sentence_audio: None,
+                picture: None,
+            });
+        }
+    }
+
+    let mut debug_file = OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .create(true)
+        .open(debug_path)
+        .map_err(Error::io(debug_path))?;
+    debug_file
+        .write_all(debug_text.as_bytes())
+        .map_err(Error::io(debug_path))?;
+
+    let mut result_counter_file = OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .create(true)
+        .open(result_counter_path)
+        .map_err(Error::io(result_counter_path))?;
+    result_counter_file
+        .write_all(result_counter_text.as_bytes())
+        .map_err(Error::io(result_counter_path))?;
+
+    Ok(ret)
+}
+
+/// The lemma the word list is matched against, without the trailing だ or い
+/// of adjectives and auxiliary verbs.
+fn sanitized_lemma(m: &Morpheme) -> String {
+    let mut lemma = m.lemma.clone();
+    if m.pos == "形容詞" || m.pos == "助動詞" {
+        // adjective or aux verb
+        lemma.pop();
+    }
+    lemma
+}
+
+/// How words are compared with the word list, so that a looked up ｶﾀｶﾅ or
+/// カタカナ word matches the かたかな of the analyzer and the other way round.
+fn normalize(word: &str) -> String {
+    katakana_to_hiragana(&word.nfkc().collect::<String>())
+}
+
+/// Indexes the `0012 word` lines of the word list by sentence number, so each
+/// token is looked up in the few words of its own sentence.
+fn index_word_list(words_string: &str) -> HashMap<usize, Vec<String>> {
+    let mut index: HashMap<usize, Vec<String>> = HashMap::new();
+    for line in words_string.lines() {
+        let (number, word) = match line.trim().split_once(' ') {
+            Some(x) => x,
+            None => continue,
+        };
+        let word = word.trim();
+        match number.parse() {
+            Ok(number) if !word.is_empty() => {
+                index.entry(number).or_default().push(normalize(word))
+            }
+            _ => continue,
+        }
+    }
+    index
+}
+
+/// Finds the first run of at least two morphemes spelling the normalized
+/// `word`, the last one either as written or in its dictionary form.
+fn find_span(morphemes: &[Morpheme], word: &str) -> Option<Range<usize>> {
+    for start in 0..morphemes.len() {
+        let mut prefix = String::new();
+        for (end, last) in morphemes.iter().enumerate().skip(start) {
+            if end > start
+                && [&last.surface, &last.lemma, &sanitized_lemma(last)]
+                    .iter()
+                    .any(|w| normalize(&format!("{}{}", prefix, w)) == word)
+            {
+                return Some(start..end + 1);
+            }
+            prefix += &last.surface;
+            if !word.starts_with(&normalize(&prefix)) {
+                break;
+            }
+        }
+    }
+    None
+}
+
+/// Builds the word of a span of morphemes: the readings are concatenated and
+/// only the last morpheme is turned back into its dictionary form.
+fn span_information(span: &[Morpheme]) -> WordInformation {
+    let (last, init) = span.split_last().expect("spans are never empty");
+    let prefix: String = init.iter().map(|m| m.surface.as_str()).collect();
+    let prefix_reading: String = init.iter().map(|m| m.reading.as_str()).collect();
+    let original = format!("{}{}", prefix, last.surface);
+
+    let p = match &last.canonical_form {
+        Some(c) => get_canonical_reading(
+            &format!("{}{}", prefix, c.kanji),
+            &format!("{}{}", prefix_reading, c.reading),
+            &last.pos,
+        ),
+        None => get_reading_stem(
+            &original,
+            &format!("{}{}", prefix_reading, last.reading),
+            &format!("{}{}", prefix, last.lemma),
+            &last.pos,
+        ),
+    };
+
+    WordInformation {
+        original,
+        dictionary_form: p.word,
+        reading_kana: p.kana,
+        reading_furigana: p.furigana,
+        pos: last.pos.clone(),
+        pos_information: last.sub_pos.clone(),
+        normalized_form: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn morpheme(surface: &str, reading: &str, lemma: &str, pos: &str) -> Morpheme {
+        Morpheme {
+            surface: surface.to_string(),
+            reading: reading.to_string(),
+            lemma: lemma.to_string(),
+            pos: pos.to_string(),
+            sub_pos: "*".to_string(),
+            conjugation_type: "*".to_string(),
+            conjugation_form: "*".to_string(),
+            semantic_info: "NIL".to_string(),
+            normalized_form: None,
+            canonical_form: None,
+            alternatives: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_index_word_list() {
+        let index = index_word_list(
+            "0001 取り扱い\n0012 気がつく\n0112 一生懸命\n0012 a.b*\n0012 ﾄﾞｱ\n0013 ドア\n",
+        );
+        assert_eq!(index[&12], vec!["気がつく", "a.b*", "どあ"]);
+        assert_eq!(index[&13], vec!["どあ"]);
+        assert_eq!(index[&112], vec!["一生懸命"]);
+        assert_eq!(index.get(&2), None);
+    }
+
+    #[test]
+    fn test_span_conjugated() {
+        let morphemes = vec![
+            morpheme("彼", "かれ", "彼", "名詞"),
+            morpheme("は", "は", "は", "助詞"),
+            morpheme("気", "き", "気", "名詞"),
+            morpheme("が", "が", "が", "助詞"),
+            morpheme("つい", "つい", "つく", "動詞"),
+            morpheme("た", "た", "た", "接尾辞"),
+        ];
+        let span = find_span(&morphemes, "気がつく").unwrap();
+        assert_eq!(span, 2..5);
+
+        let w = span_information(&morphemes[span]);
+        assert_eq!(w.original, "気がつい");
+        assert_eq!(w.dictionary_form, "気がつく");
+        assert_eq!(w.reading_kana, "きがつく");
+        assert_eq!(w.pos, "動詞");
+    }
+
+    #[test]
+    fn test_span_single_token() {
+        let morphemes = vec![morpheme(
+            "一生懸命",
+            "いっしょうけんめい",
+            "一生懸命",
+            "形容詞",
+        )];
+        assert_eq!(find_span(&morphemes, "一生懸命"), None);
+    }
+
+    #[test]
+    fn test_mine_alternative() {
+        // Juman++ may read 描いた as 書く, with 描く only as an @ alternative
+        let mut m = morpheme("描いた", "かいた", "書く", "動詞");
+        m.alternatives = vec![morpheme("描いた", "えがいた", "描く", "動詞")];
+        let dir = std::env::temp_dir().join(format!("gd2anki_test_mining_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let word_list = dir.join("words.txt");
+        let debug = dir.join("debug.txt");
+        let counter = dir.join("counter.txt");
+        fs::write(&word_list, "0000 描く\n").unwrap();
+
+        let exports = mine_sentences(
+            &[vec![m]],
+            word_list.to_str().unwrap(),
+            debug.to_str().unwrap(),
+            counter.to_str().unwrap(),
+        )
+        .unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(exports.len(), 1);
+        assert_eq!(exports[0].word.dictionary_form, "描く");
+        assert_eq!(exports[0].word.reading_kana, "えがく");
+    }
+}
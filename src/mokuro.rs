@@ -0,0 +1,268 @@
+use log::warn;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, Result};
+use crate::gdict::Vocab;
+use crate::pairing::{find_containing, read_numbered, write_numbered, Decision, Pairing};
+
+/// The extensions tried when looking for the image of a page.
+const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "webp"];
+
+/// A volume as written by mokuro into `<volume>.mokuro`, its images being in
+/// the `<volume>` directory next to it.
+#[derive(Debug, Deserialize)]
+pub struct MokuroVolume {
+    pub pages: Vec<MokuroPage>,
+}
+
+/// The OCR of one page, also written on its own as `_ocr/<volume>/<page>.json`
+/// by older versions of mokuro, without `img_path`.
+#[derive(Debug, Deserialize)]
+pub struct MokuroPage {
+    pub img_path: Option<String>,
+    pub img_width: u32,
+    pub img_height: u32,
+    pub blocks: Vec<MokuroBlock>,
+}
+
+/// A text box, usually a speech bubble.
+#[derive(Debug, Deserialize)]
+pub struct MokuroBlock {
+    /// left, top, right and bottom in pixels
+    #[serde(rename = "box")]
+    pub bounds: [f64; 4],
+    pub lines: Vec<String>,
+}
+
+/// Where a sentence is on a page image, in pixels.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Region {
+    pub image: String,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// The text of a box and where it is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextBox {
+    pub text: String,
+    pub region: Region,
+}
+
+/// The text boxes of `page`, whose image is `image`, clamped to the page.
+pub fn page_boxes(page: &MokuroPage, image: &str) -> Vec<TextBox> {
+    page.blocks
+        .iter()
+        .filter_map(|block| {
+            let text: String = block.lines.iter().map(|l| l.trim()).collect();
+            let [left, top, right, bottom] = block.bounds;
+            let x = (left.max(0.0) as u32).min(page.img_width);
+            let y = (top.max(0.0) as u32).min(page.img_height);
+            let width = (right.max(0.0).ceil() as u32)
+                .min(page.img_width)
+                .checked_sub(x)?;
+            let height = (bottom.max(0.0).ceil() as u32)
+                .min(page.img_height)
+                .checked_sub(y)?;
+            if text.is_empty() || width == 0 || height == 0 {
+                return None;
+            }
+            Some(TextBox {
+                text,
+                region: Region {
+                    image: image.to_string(),
+                    x,
+                    y,
+                    width,
+                    height,
+                },
+            })
+        })
+        .collect()
+}
+
+/// Finds the image of the page json at `path`: in `images` when given, next to
+/// it, or in the volume directory of mokuro's `_ocr/<volume>/<page>.json`.
+fn find_page_image(path: &Path, images: Option<&str>) -> Option<PathBuf> {
+    let stem = path.file_stem()?.to_str()?;
+    let dir = path.parent()?;
+    let mut dirs = Vec::new();
+    match images {
+        Some(images) => dirs.push(PathBuf::from(images)),
+        None => {
+            dirs.push(dir.to_path_buf());
+            if let (Some(volume), Some(ocr)) = (dir.file_name(), dir.parent()) {
+                if ocr.file_name()? == "_ocr" {
+                    dirs.push(ocr.parent()?.join(volume));
+                }
+            }
+        }
+    }
+    dirs.iter()
+        .flat_map(|dir| {
+            IMAGE_EXTENSIONS
+                .iter()
+                .map(move |e| dir.join(format!("{}.{}", stem, e)))
+        })
+        .find(|image| image.is_file())
+}
+
+fn read_page(path: &Path, images: Option<&str>) -> Result<Vec<TextBox>> {
+    let name = path.to_string_lossy();
+    let text = fs::read_to_string(path).map_err(Error::io(&name))?;
+    let page: MokuroPage = serde_json::from_str(&text).map_err(Error::json(&name))?;
+    match find_page_image(path, images) {
+        Some(image) => Ok(page_boxes(&page, &image.to_string_lossy())),
+        None => {
+            warn!("skipping {}, its page image was not found", name);
+            Ok(Vec::new())
+        }
+    }
+}
+
+/// Reads the text boxes of a `.mokuro` volume, of a page json, or of every
+/// page json in a directory, in page order. The page images are looked up in
+/// `images` when given.
+pub fn read_mokuro(path: &str, images: Option<&str>) -> Result<Vec<TextBox>> {
+    let p = Path::new(path);
+    if p.is_dir() {
+        let mut pages: Vec<PathBuf> = fs::read_dir(p)
+            .map_err(Error::io(path))?
+            .map(|entry| entry.map(|e| e.path()).map_err(Error::io(path)))
+            .collect::<Result<_>>()?;
+        pages.retain(|page| page.extension().is_some_and(|e| e == "json"));
+        pages.sort();
+        let mut boxes = Vec::new();
+        for page in pages {
+            boxes.extend(read_page(&page, images)?);
+        }
+        return Ok(boxes);
+    }
+    if p.extension().is_some_and(|e| e == "json") {
+        return read_page(p, images);
+    }
+
+    let text = fs::read_to_string(path).map_err(Error::io(path))?;
+    let volume: MokuroVolume = serde_json::from_str(&text).map_err(Error::json(path))?;
+    let images = match images {
+        Some(images) => PathBuf::from(images),
+        None => p.with_extension(""),
+    };
+    Ok(volume
+        .pages
+        .iter()
+        .flat_map(|page| {
+            let image = images.join(page.img_path.as_deref().unwrap_or_default());
+            page_boxes(page, &image.to_string_lossy())
+        })
+        .collect())
+}
+
+/// Pairs each of `words` with the first text box containing it, and returns
+/// the region of every sentence by its number.
+pub fn pair_boxes(boxes: &[TextBox], words: &[String]) -> (Pairing, HashMap<usize, Region>) {
+    let mut pairing = Pairing::default();
+    let mut regions = HashMap::new();
+
+    for word in words.iter().map(|w| w.trim()).filter(|w| !w.is_empty()) {
+        let text_box = match find_containing(boxes, word, |b| &b.text) {
+            Some(text_box) => text_box,
+            None => {
+                pairing.decisions.push(Decision::Dropped {
+                    word: word.to_string(),
+                    reason: "not in the manga".to_string(),
+                });
+                continue;
+            }
+        };
+        let vocab = Vocab {
+            kanji: word.to_string(),
+            sentence: text_box.text.clone(),
+        };
+        let reason = format!("on page {}", text_box.region.image);
+        let number = pairing.add_pair(&vocab, &reason);
+        regions
+            .entry(number)
+            .or_insert_with(|| text_box.region.clone());
+    }
+
+    (pairing, regions)
+}
+
+/// Writes the regions as `0012<TAB>x<TAB>y<TAB>width<TAB>height<TAB>image`
+/// lines.
+pub fn write_regions(path: &str, regions: &HashMap<usize, Region>) -> Result<()> {
+    write_numbered(path, regions, |r| {
+        vec![
+            r.x.to_string(),
+            r.y.to_string(),
+            r.width.to_string(),
+            r.height.to_string(),
+            r.image.clone(),
+        ]
+    })
+}
+
+/// Reads the regions written by `write_regions`.
+pub fn read_regions(path: &str) -> Result<HashMap<usize, Region>> {
+    read_numbered(path, 5, "region", |v| {
+        Some(Region {
+            x: v[0].parse().ok()?,
+            y: v[1].parse().ok()?,
+            width: v[2].parse().ok()?,
+            height: v[3].parse().ok()?,
+            image: v[4].to_string(),
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE: &str = r#"{"version": "0.1.7", "img_width": 1000, "img_height": 1500, "blocks": [
+        {"box": [800, 100, 900, 400], "vertical": true, "font_size": 30.0,
+         "lines_coords": [], "lines": ["道を", "尋ねた"]},
+        {"box": [950, 1400, 1100, 1600], "vertical": true, "font_size": 30.0,
+         "lines_coords": [], "lines": ["友達だ．．．"]},
+        {"box": [10, 10, 20, 20], "vertical": false, "font_size": 12.0,
+         "lines_coords": [], "lines": [" "]}
+    ]}"#;
+
+    #[test]
+    fn test_page_boxes() {
+        let page: MokuroPage = serde_json::from_str(PAGE).unwrap();
+        let boxes = page_boxes(&page, "vol1/001.jpg");
+        assert_eq!(boxes.len(), 2);
+        assert_eq!(boxes[0].text, "道を尋ねた");
+        assert_eq!(
+            boxes[1].region,
+            Region {
+                image: "vol1/001.jpg".to_string(),
+                x: 950,
+                y: 1400,
+                width: 50,
+                height: 100,
+            }
+        );
+    }
+
+    #[test]
+    fn test_pair_boxes() {
+        let page: MokuroPage = serde_json::from_str(PAGE).unwrap();
+        let boxes = page_boxes(&page, "vol1/001.jpg");
+        let words = vec!["尋ねる".to_string(), "猫".to_string()];
+        let (pairing, regions) = pair_boxes(&boxes, &words);
+        assert_eq!(pairing.sentences, vec!["道を尋ねた"]);
+        assert_eq!(regions[&0].x, 800);
+        assert!(pairing
+            .decisions
+            .iter()
+            .any(|d| matches!(d, Decision::Dropped { word, .. } if word == "猫")));
+    }
+}
@@ -0,0 +1,465 @@
+use log::warn;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+
+use crate::error::{Error, Result};
+use crate::gdict::Vocab;
+
+/// The characters a headword may end with to count as a sentence.
+const SENTENCE_ENDINGS: &[char] = &['。', '．', '.', '！', '？', '!', '?', '」', '』', '…'];
+
+/// How headwords are told apart from sentences, and which sentence a word
+/// belongs to. A headword is a sentence as soon as one of the rules says so.
+#[derive(Debug, Clone)]
+pub struct PairingRules {
+    /// headwords longer than this many characters are sentences
+    pub max_word_len: Option<usize>,
+    /// headwords ending in sentence punctuation, e.g. 。 or ？, are sentences
+    pub sentence_punctuation: bool,
+    /// headwords containing 。 or 、 are sentences
+    pub clause_punctuation: bool,
+    /// headwords starting with this prefix are sentences, the prefix removed
+    pub marker: Option<String>,
+    /// pair words with the sentence looked up after them instead of before
+    pub words_before_sentence: bool,
+}
+
+impl Default for PairingRules {
+    fn default() -> PairingRules {
+        PairingRules {
+            max_word_len: Some(7),
+            sentence_punctuation: false,
+            clause_punctuation: false,
+            marker: None,
+            words_before_sentence: false,
+        }
+    }
+}
+
+impl PairingRules {
+    /// Returns the sentence with the marker removed and the rule that made it
+    /// one, or `None` for a word.
+    fn sentence<'a>(&self, headword: &'a str) -> Option<(&'a str, String)> {
+        if let Some(marker) = self.marker.as_deref().filter(|m| !m.is_empty()) {
+            if let Some(sentence) = headword.strip_prefix(marker) {
+                return Some((sentence.trim(), format!("starts with {:?}", marker)));
+            }
+        }
+        if self.sentence_punctuation && headword.trim_end().ends_with(SENTENCE_ENDINGS) {
+            return Some((headword, "ends in sentence punctuation".to_string()));
+        }
+        if self.clause_punctuation && headword.contains(&['。', '、'][..]) {
+            return Some((headword, "contains 。 or 、".to_string()));
+        }
+        match self.max_word_len {
+            Some(len) if headword.chars().count() > len => {
+                Some((headword, format!("longer than {} characters", len)))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// What happened to one headword or explicit pair.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Decision {
+    Sentence {
+        number: usize,
+        sentence: String,
+        reason: String,
+    },
+    Word {
+        sentence: usize,
+        word: String,
+        reason: String,
+    },
+    Dropped {
+        word: String,
+        reason: String,
+    },
+}
+
+impl fmt::Display for Decision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Decision::Sentence {
+                number,
+                sentence,
+                reason,
+            } => write!(f, "sentence {:04}: {} ({})", number, sentence, reason),
+            Decision::Word {
+                sentence,
+                word,
+                reason,
+            } => write!(f, "word for {:04}: {} ({})", sentence, word, reason),
+            Decision::Dropped { word, reason } => write!(f, "dropped: {} ({})", word, reason),
+        }
+    }
+}
+
+/// Sentences and the words looked up in them, numbered like the sentence file.
+#[derive(Debug, Default, PartialEq)]
+pub struct Pairing {
+    pub sentences: Vec<String>,
+    pub vocabs: Vec<(usize, String)>,
+    pub decisions: Vec<Decision>,
+    /// the number of each sentence, for `add_pair`
+    numbers: HashMap<String, usize>,
+}
+
+impl Pairing {
+    fn push_sentence(&mut self, sentence: &str, reason: String) -> usize {
+        self.sentences.push(sentence.to_string());
+        let number = self.sentences.len() - 1;
+        self.numbers.entry(sentence.to_string()).or_insert(number);
+        self.decisions.push(Decision::Sentence {
+            number,
+            sentence: sentence.to_string(),
+            reason,
+        });
+        number
+    }
+
+    /// Pairs the word of `vocab` with its sentence, reusing a sentence already
+    /// seen, and returns the number of the sentence.
+    pub fn add_pair(&mut self, vocab: &Vocab, reason: &str) -> usize {
+        let number = match self.numbers.get(&vocab.sentence) {
+            Some(&number) => number,
+            None => self.push_sentence(&vocab.sentence, reason.to_string()),
+        };
+        self.push_word(number, &vocab.kanji, reason.to_string());
+        number
+    }
+
+    fn push_word(&mut self, sentence: usize, word: &str, reason: String) {
+        self.vocabs.push((sentence, word.to_string()));
+        self.decisions.push(Decision::Word {
+            sentence,
+            word: word.to_string(),
+            reason,
+        });
+    }
+
+    /// Writes the sentences one per line to `sentences_path`, the words as
+    /// `0012 word` lines to `vocabs_path` and, if given, every decision to
+    /// `report_path`.
+    pub fn write(
+        &self,
+        vocabs_path: &str,
+        sentences_path: &str,
+        report_path: Option<&str>,
+    ) -> Result<()> {
+        let mut vocabs = self.vocabs.clone();
+        vocabs.sort_by_key(|(sentence, _)| *sentence);
+        let vocabs: String = vocabs
+            .iter()
+            .map(|(sentence, word)| format!("{:04} {}\n", sentence, word))
+            .collect();
+        let sentences: String = self.sentences.iter().map(|s| format!("{}\n", s)).collect();
+
+        write_file(vocabs_path, &vocabs)?;
+        write_file(sentences_path, &sentences)?;
+        if let Some(report_path) = report_path {
+            let report: String = self.decisions.iter().map(|d| format!("{}\n", d)).collect();
+            write_file(report_path, &report)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes `text` to `path`, replacing the file if it exists.
+pub fn write_file(path: &str, text: &str) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .create(true)
+        .open(path)
+        .map_err(Error::io(path))?;
+    file.write_all(text.as_bytes()).map_err(Error::io(path))
+}
+
+/// Writes a side file of `rows` keyed by sentence number, as
+/// `0012<TAB>field<TAB>…` lines in sentence order.
+pub fn write_numbered<T>(
+    path: &str,
+    rows: &HashMap<usize, T>,
+    fields: impl Fn(&T) -> Vec<String>,
+) -> Result<()> {
+    let mut numbers: Vec<&usize> = rows.keys().collect();
+    numbers.sort();
+    let text: String = numbers
+        .into_iter()
+        .map(|n| format!("{:04}\t{}\n", n, fields(&rows[n]).join("\t")))
+        .collect();
+    write_file(path, &text)
+}
+
+/// Reads a side file written by `write_numbered`. Each line is split into its
+/// number and `count` fields for `parse`, and lines that don't parse are
+/// skipped with a warning calling them `not a <what>`.
+pub fn read_numbered<T>(
+    path: &str,
+    count: usize,
+    what: &str,
+    parse: impl Fn(&[&str]) -> Option<T>,
+) -> Result<HashMap<usize, T>> {
+    let text = fs::read_to_string(path).map_err(Error::io(path))?;
+
+    let mut rows = HashMap::new();
+    for (line, x) in text.lines().enumerate() {
+        let v: Vec<&str> = x.splitn(count + 1, '\t').collect();
+        let row = match v.split_first() {
+            Some((number, fields)) if fields.len() == count => {
+                number.parse::<usize>().ok().zip(parse(fields))
+            }
+            _ => None,
+        };
+        match row {
+            Some((number, row)) => {
+                rows.insert(number, row);
+            }
+            None => warn!(
+                "skipping {}",
+                Error::line(path, line + 1, x, &format!("not a {}", what))
+            ),
+        }
+    }
+    Ok(rows)
+}
+
+/// Pairs headwords one at a time as they are read, see `pair_headwords`.
+#[derive(Debug)]
+pub struct Pairer<'a> {
+    rules: &'a PairingRules,
+    pairing: Pairing,
+    // words not yet paired, waiting for a sentence
+    pending: Vec<String>,
+}
+
+impl<'a> Pairer<'a> {
+    pub fn new(rules: &'a PairingRules) -> Pairer<'a> {
+        Pairer {
+            rules,
+            pairing: Pairing::default(),
+            pending: Vec::new(),
+        }
+    }
+
+    /// Takes the next headword in the order they were looked up.
+    pub fn push(&mut self, headword: &str) {
+        let headword = headword.trim();
+        if headword.is_empty() {
+            return;
+        }
+        let pairing = &mut self.pairing;
+        match self.rules.sentence(headword) {
+            Some((sentence, reason)) => {
+                let number = pairing.push_sentence(sentence, reason);
+                for word in self.pending.drain(..) {
+                    pairing.push_word(number, &word, "looked up before its sentence".to_string());
+                }
+            }
+            None if self.rules.words_before_sentence || pairing.sentences.is_empty() => {
+                self.pending.push(headword.to_string())
+            }
+            None => {
+                let number = pairing.sentences.len() - 1;
+                pairing.push_word(number, headword, "looked up after its sentence".to_string());
+            }
+        }
+    }
+
+    /// Pairs the words left over, then adds the `explicit` pairs, reusing a
+    /// sentence already seen.
+    pub fn finish(self, explicit: &[Vocab]) -> Pairing {
+        let mut pairing = self.pairing;
+
+        for word in self.pending {
+            match pairing.sentences.len().checked_sub(1) {
+                Some(number) => pairing.push_word(
+                    number,
+                    &word,
+                    "no sentence after it, paired with the last".to_string(),
+                ),
+                None => pairing.decisions.push(Decision::Dropped {
+                    word,
+                    reason: "no sentence was looked up".to_string(),
+                }),
+            }
+        }
+
+        for vocab in explicit {
+            pairing.add_pair(vocab, "explicit pair");
+        }
+
+        pairing
+    }
+}
+
+/// Splits `headwords`, in the order they were looked up, into sentences and
+/// words following `rules`, then adds the `explicit` pairs, reusing a sentence
+/// already seen.
+pub fn pair_headwords(headwords: &[String], explicit: &[Vocab], rules: &PairingRules) -> Pairing {
+    let mut pairer = Pairer::new(rules);
+    for headword in headwords {
+        pairer.push(headword);
+    }
+    pairer.finish(explicit)
+}
+
+/// Like `pair_headwords`, for headwords read one at a time, stopping at the
+/// first error.
+pub fn pair_all(
+    headwords: impl IntoIterator<Item = Result<String>>,
+    explicit: &[Vocab],
+    rules: &PairingRules,
+) -> Result<Pairing> {
+    let mut pairer = Pairer::new(rules);
+    for headword in headwords {
+        pairer.push(&headword?);
+    }
+    Ok(pairer.finish(explicit))
+}
+
+/// Finds the first of `items` whose `text` contains `word`, or its stem for a
+/// word ending in kana, e.g. 尋ね for 尋ねる in 尋ねた. A stem without kanji,
+/// す for する, would be found almost anywhere, so kana words must match whole.
+pub fn find_containing<T, I>(items: I, word: &str, text: impl Fn(&T) -> &str) -> Option<T>
+where
+    I: IntoIterator<Item = T> + Clone,
+{
+    if let Some(item) = items.clone().into_iter().find(|i| text(i).contains(word)) {
+        return Some(item);
+    }
+    let mut stem = word.to_string();
+    let last = stem.pop()?;
+    if !stem.chars().any(|c| kanji::is_kanji(&c)) || !matches!(last, 'ぁ'..='ゖ') {
+        return None;
+    }
+    items.into_iter().find(|i| text(i).contains(&stem))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headwords(v: &[&str]) -> Vec<String> {
+        v.iter().map(|h| h.to_string()).collect()
+    }
+
+    #[test]
+    fn test_default_rules() {
+        let p = pair_headwords(
+            &headwords(&["友達", "彼は駅で友達に道を尋ねていた。", "尋ねる", "駅"]),
+            &[],
+            &PairingRules::default(),
+        );
+        assert_eq!(p.sentences, vec!["彼は駅で友達に道を尋ねていた。"]);
+        assert_eq!(
+            p.vocabs,
+            vec![
+                (0, "友達".to_string()),
+                (0, "尋ねる".to_string()),
+                (0, "駅".to_string())
+            ]
+        );
+        assert_eq!(p.decisions.len(), 4);
+    }
+
+    #[test]
+    fn test_short_sentence_and_long_word() {
+        let rules = PairingRules {
+            max_word_len: None,
+            sentence_punctuation: true,
+            ..PairingRules::default()
+        };
+        let p = pair_headwords(
+            &headwords(&["行こう！", "独立行政法人国立病院機構"]),
+            &[],
+            &rules,
+        );
+        assert_eq!(p.sentences, vec!["行こう！"]);
+        assert_eq!(p.vocabs, vec![(0, "独立行政法人国立病院機構".to_string())]);
+    }
+
+    #[test]
+    fn test_marker_and_words_before() {
+        let rules = PairingRules {
+            marker: Some("#".to_string()),
+            words_before_sentence: true,
+            ..PairingRules::default()
+        };
+        let p = pair_headwords(
+            &headwords(&["友達", "#ただいま", "駅", "#おかえり"]),
+            &[],
+            &rules,
+        );
+        assert_eq!(p.sentences, vec!["ただいま", "おかえり"]);
+        assert_eq!(
+            p.vocabs,
+            vec![(0, "友達".to_string()), (1, "駅".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_explicit_pairs() {
+        let p = pair_headwords(
+            &headwords(&["彼は駅で友達に道を尋ねていた。"]),
+            &[
+                Vocab {
+                    kanji: "道".to_string(),
+                    sentence: "彼は駅で友達に道を尋ねていた。".to_string(),
+                },
+                Vocab {
+                    kanji: "猫".to_string(),
+                    sentence: "猫がいる".to_string(),
+                },
+            ],
+            &PairingRules::default(),
+        );
+        assert_eq!(p.sentences.len(), 2);
+        assert_eq!(p.vocabs, vec![(0, "道".to_string()), (1, "猫".to_string())]);
+    }
+
+    #[test]
+    fn test_find_containing() {
+        let sentences = ["道を尋ねた。", "すぐ行く。"];
+        assert_eq!(
+            find_containing(sentences, "尋ねる", |s| s),
+            Some("道を尋ねた。")
+        );
+        assert_eq!(find_containing(sentences, "する", |s| s), None);
+        assert_eq!(
+            find_containing(sentences, "行く", |s| s),
+            Some("すぐ行く。")
+        );
+    }
+
+    #[test]
+    fn test_numbered_side_file() {
+        let dir =
+            std::env::temp_dir().join(format!("gd2anki_test_numbered_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("side.tsv");
+        let path = path.to_str().unwrap();
+
+        let rows: HashMap<usize, (u32, String)> =
+            vec![(12, (3, "a\tb".to_string())), (2, (7, "c".to_string()))]
+                .into_iter()
+                .collect();
+        write_numbered(path, &rows, |(n, s)| vec![n.to_string(), s.clone()]).unwrap();
+        let text = fs::read_to_string(path).unwrap();
+        fs::write(path, format!("{}x\t1\tz\n0003\t1\n", text)).unwrap();
+
+        let read = read_numbered(path, 2, "row", |v| {
+            Some((v[0].parse::<u32>().ok()?, v[1].to_string()))
+        })
+        .unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(text, "0002\t7\tc\n0012\t3\ta\tb\n");
+        assert_eq!(read, rows);
+    }
+}
@@ -0,0 +1,423 @@
+use encoding_rs::SHIFT_JIS;
+use log::warn;
+use regex::Regex;
+use std::collections::HashMap;
+use std::fs;
+use std::sync::LazyLock;
+
+use crate::analyzer::Morpheme;
+use crate::error::{Error, Result};
+use crate::gdict::Vocab;
+use crate::pairing::{find_containing, write_file, Decision, Pairing};
+use crate::utils::katakana_to_hiragana;
+
+// EPUBs often give them a class, <ruby class="ruby"><rt class="rt">
+static RE_RUBY: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?s)<ruby(?:\s[^>]*)?>(.*?)</ruby>").unwrap());
+static RE_RP: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?s)<rp(?:\s[^>]*)?>.*?</rp>").unwrap());
+static RE_RT: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?s)<rt(?:\s[^>]*)?>(.*?)</rt>").unwrap());
+static RE_TAG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<[^>]*>").unwrap());
+static RE_NOTE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"※?［＃[^］]*］").unwrap());
+static RE_ENTITY: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"&(nbsp|lt|gt|quot|apos|amp|#x[0-9a-fA-F]+|#[0-9]+);").unwrap());
+
+/// The author's reading of each annotated word, e.g. 竈門 -> かまど.
+pub type ReadingHints = HashMap<String, String>;
+
+/// Whether `c` is part of the kanji run an Aozora ruby without `｜` applies to.
+fn is_ruby_base(c: char) -> bool {
+    kanji::is_kanji(&c) || matches!(c, '々' | '〆' | 'ヵ' | 'ヶ')
+}
+
+fn is_kana(c: char) -> bool {
+    matches!(c, 'ぁ'..='ゖ' | 'ァ'..='ヺ' | 'ー')
+}
+
+fn is_terminator(c: char) -> bool {
+    matches!(c, '。' | '！' | '？' | '!' | '?')
+}
+
+fn add_hint(hints: &mut ReadingHints, base: &str, reading: &str) {
+    let reading = katakana_to_hiragana(reading.trim());
+    if !base.is_empty() && !reading.is_empty() {
+        // the first reading wins, later ones are usually the same anyway
+        hints.entry(base.to_string()).or_insert(reading);
+    }
+}
+
+/// Whether `c` following a ruby base is rather a particle or the copula than
+/// okurigana. か, ね and よ are left out, being okurigana as often, 分かる.
+fn is_particle(c: char) -> bool {
+    matches!(
+        c,
+        'は' | 'が' | 'を' | 'に' | 'の' | 'へ' | 'も' | 'と' | 'で' | 'や' | 'だ'
+    )
+}
+
+/// Adds the hints of the rubies found in `text`, as the byte ranges of their
+/// bases with their readings. A kanji base followed by okurigana is added
+/// with the first kana of it instead, 生ま -> うま for 生《う》まれる, which is
+/// what `hinted_reading` matches inflected words against, so that 生 alone
+/// keeps the reading of the analyzer.
+fn add_hints(hints: &mut ReadingHints, text: &str, rubies: &[(usize, usize, String)]) {
+    for (start, end, reading) in rubies {
+        let base = &text[*start..*end];
+        let last = base.chars().last();
+        match text[*end..].chars().next() {
+            Some(next @ 'ぁ'..='ゖ') if last.is_some_and(is_ruby_base) && !is_particle(next) => {
+                add_hint(
+                    hints,
+                    &format!("{}{}", base, next),
+                    &format!("{}{}", reading.trim(), next),
+                )
+            }
+            _ => add_hint(hints, base, reading),
+        }
+    }
+}
+
+/// Decodes the character references of html text in a single pass, so that
+/// `&amp;#x41;` stays `&#x41;`. Unknown ones are kept as they are.
+fn decode_entities(text: &str) -> String {
+    RE_ENTITY
+        .replace_all(text, |caps: &regex::Captures| {
+            let code = match &caps[1] {
+                "nbsp" => Some(' ' as u32),
+                "lt" => Some('<' as u32),
+                "gt" => Some('>' as u32),
+                "quot" => Some('"' as u32),
+                "apos" => Some('\'' as u32),
+                "amp" => Some('&' as u32),
+                number => match number.strip_prefix("#x") {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => number[1..].parse().ok(),
+                },
+            };
+            code.and_then(std::char::from_u32)
+                .map_or_else(|| caps[0].to_string(), String::from)
+        })
+        .into_owned()
+}
+
+/// Replaces the `<ruby>` elements of web novels by their base text, adding
+/// their readings to `hints`, and removes every other tag.
+pub fn parse_html_ruby(line: &str, hints: &mut ReadingHints) -> String {
+    let clean = |html: &str| decode_entities(&RE_TAG.replace_all(html, ""));
+
+    let mut text = String::new();
+    let mut rubies = Vec::new();
+    let mut rest = 0;
+    for ruby in RE_RUBY.captures_iter(line) {
+        let whole = ruby.get(0).expect("group 0 always matches");
+        text += &clean(&line[rest..whole.start()]);
+        rest = whole.end();
+
+        let inner = RE_RP.replace_all(&ruby[1], "");
+        let mut inner_rest = 0;
+        // <ruby>漢字<rt>かんじ</rt></ruby> or <ruby><rb>漢</rb><rt>かん</rt>...
+        for rt in RE_RT.captures_iter(&inner) {
+            let rt_whole = rt.get(0).expect("group 0 always matches");
+            let start = text.len();
+            text += &clean(&inner[inner_rest..rt_whole.start()]);
+            rubies.push((start, text.len(), rt[1].to_string()));
+            inner_rest = rt_whole.end();
+        }
+        text += &clean(&inner[inner_rest..]);
+    }
+    text += &clean(&line[rest..]);
+    add_hints(hints, &text, &rubies);
+    text
+}
+
+/// Removes the ruby of a line of Aozora Bunko or Narou text, `｜漢字《かんじ》`
+/// or `漢字《かんじ》`, adding the readings to `hints`. `<ruby>` markup and
+/// Aozora's `［＃...］` notes are removed too.
+pub fn parse_ruby(line: &str, hints: &mut ReadingHints) -> String {
+    let line = parse_html_ruby(&RE_NOTE.replace_all(line, ""), hints);
+
+    let mut text = String::new();
+    let mut rubies = Vec::new();
+    // where the base of the next ruby starts in `text`, after a ｜
+    let mut base_start = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '｜' | '|' => base_start = Some(text.len()),
+            '《' => {
+                let reading: String = chars.by_ref().take_while(|&c| c != '》').collect();
+                let start = base_start.take().unwrap_or_else(|| {
+                    text.char_indices()
+                        .rev()
+                        .take_while(|&(_, c)| is_ruby_base(c))
+                        .last()
+                        .map_or(text.len(), |(i, _)| i)
+                });
+                rubies.push((start, text.len(), reading));
+            }
+            c => text.push(c),
+        }
+    }
+    add_hints(hints, &text, &rubies);
+    text
+}
+
+/// Splits a paragraph after each run of `。！？` outside of brackets, so that
+/// 「行くぞ！」と言った。 and 本当？！ stay one sentence each.
+pub fn split_sentences(paragraph: &str) -> Vec<String> {
+    let mut sentences = Vec::new();
+    let mut sentence = String::new();
+    let mut depth = 0usize;
+    let mut ended = false;
+    for c in paragraph.chars() {
+        if ended && !is_terminator(c) {
+            sentences.push(sentence.trim().to_string());
+            sentence.clear();
+            ended = false;
+        }
+        sentence.push(c);
+        match c {
+            '「' | '『' | '（' | '(' => depth += 1,
+            '」' | '』' | '）' | ')' => depth = depth.saturating_sub(1),
+            c if is_terminator(c) && depth == 0 => ended = true,
+            _ => {}
+        }
+    }
+    sentences.push(sentence.trim().to_string());
+    sentences.retain(|s| !s.is_empty());
+    sentences
+}
+
+/// Decodes a novel, which is Shift_JIS when it comes from Aozora Bunko.
+fn decode_text(bytes: &[u8]) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        Err(_) => SHIFT_JIS.decode_without_bom_handling(bytes).0.into_owned(),
+    }
+}
+
+/// Reads a ruby annotated novel into its sentences and reading hints. The
+/// notation legend between the `-----` lines at the top of Aozora texts and
+/// the `底本：` colophon are skipped.
+pub fn read_ruby_text(path: &str) -> Result<(Vec<String>, ReadingHints)> {
+    let text = decode_text(&fs::read(path).map_err(Error::io(path))?);
+    let mut sentences = Vec::new();
+    let mut hints = ReadingHints::new();
+    let mut in_legend = false;
+
+    for line in text.trim_start_matches('\u{feff}').lines() {
+        let line = line.trim();
+        if line.starts_with("-----") {
+            in_legend = !in_legend;
+            continue;
+        }
+        if line.starts_with("底本：") {
+            break;
+        }
+        if in_legend {
+            continue;
+        }
+        sentences.extend(split_sentences(&parse_ruby(line, &mut hints)));
+    }
+    Ok((sentences, hints))
+}
+
+/// Pairs each of `words` with the first sentence containing it.
+pub fn pair_sentences(sentences: &[String], file: &str, words: &[String]) -> Pairing {
+    let mut pairing = Pairing::default();
+    for word in words.iter().map(|w| w.trim()).filter(|w| !w.is_empty()) {
+        match find_containing(sentences, word, |s| s) {
+            Some(sentence) => {
+                let vocab = Vocab {
+                    kanji: word.to_string(),
+                    sentence: sentence.clone(),
+                };
+                pairing.add_pair(&vocab, &format!("found in {}", file));
+            }
+            None => pairing.decisions.push(Decision::Dropped {
+                word: word.to_string(),
+                reason: format!("not in {}", file),
+            }),
+        }
+    }
+    pairing
+}
+
+/// Reads `surface` with the hints, when it is a hinted base itself or its
+/// kanji stem was hinted with the same okurigana, e.g. たずねる for 尋ねる
+/// given 尋ね -> たずね. 生きる is not read with the hint of 生《う》まれる.
+pub fn hinted_reading(surface: &str, hints: &ReadingHints) -> Option<String> {
+    if let Some(reading) = hints.get(surface) {
+        return Some(reading.clone());
+    }
+    let stem = surface.trim_end_matches(is_kana);
+    let okurigana = &surface[stem.len()..];
+    let first = okurigana.chars().next()?;
+    if stem.is_empty() {
+        return None;
+    }
+    let reading = hints.get(&format!("{}{}", stem, first))?;
+    Some(reading.clone() + &katakana_to_hiragana(&okurigana[first.len_utf8()..]))
+}
+
+fn apply_hint(m: &mut Morpheme, hints: &ReadingHints) -> bool {
+    for alternative in &mut m.alternatives {
+        apply_hint(alternative, hints);
+    }
+    let reading = match hinted_reading(&m.surface, hints) {
+        Some(reading) if reading != m.reading => reading,
+        _ => return false,
+    };
+    // the canonical reading came from the analyzer too
+    m.canonical_form = m.canonical_form.take().and_then(|mut c| {
+        if c.kanji == m.surface {
+            c.reading = reading.clone();
+            Some(c)
+        } else {
+            None
+        }
+    });
+    m.reading = reading;
+    true
+}
+
+/// Replaces the analyzer's reading of every morpheme the author's ruby reads
+/// differently, and returns how many there were. Names and 当て字 are where the
+/// analyzer is most often wrong.
+pub fn apply_reading_hints(sentences: &mut [Vec<Morpheme>], hints: &ReadingHints) -> usize {
+    sentences
+        .iter_mut()
+        .flatten()
+        .map(|m| apply_hint(m, hints))
+        .filter(|&changed| changed)
+        .count()
+}
+
+/// Writes the hints as sorted `base<TAB>reading` lines.
+pub fn write_reading_hints(path: &str, hints: &ReadingHints) -> Result<()> {
+    let mut bases: Vec<&String> = hints.keys().collect();
+    bases.sort();
+    let text: String = bases
+        .into_iter()
+        .map(|base| format!("{}\t{}\n", base, hints[base]))
+        .collect();
+    write_file(path, &text)
+}
+
+/// Reads the hints written by `write_reading_hints`.
+pub fn read_reading_hints(path: &str) -> Result<ReadingHints> {
+    let text = fs::read_to_string(path).map_err(Error::io(path))?;
+
+    let mut hints = ReadingHints::new();
+    for (line, x) in text.lines().enumerate() {
+        match x.split_once('\t') {
+            Some((base, reading)) if !base.is_empty() && !reading.is_empty() => {
+                hints.insert(base.to_string(), reading.to_string());
+            }
+            _ => warn!(
+                "skipping {}",
+                Error::line(path, line + 1, x, "not a reading hint")
+            ),
+        }
+    }
+    Ok(hints)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_aozora_ruby() {
+        let mut hints = ReadingHints::new();
+        let text = parse_ruby(
+            "｜竈門炭治郎《かまどたんじろう》は山奥《やまおく》の一軒家［＃「一軒家」に傍点］に住む。",
+            &mut hints,
+        );
+        assert_eq!(text, "竈門炭治郎は山奥の一軒家に住む。");
+        assert_eq!(hints["竈門炭治郎"], "かまどたんじろう");
+        assert_eq!(hints["山奥"], "やまおく");
+        assert_eq!(hints.len(), 2);
+    }
+
+    #[test]
+    fn test_parse_html_ruby() {
+        let mut hints = ReadingHints::new();
+        let text = parse_ruby(
+            "<ruby>魔王<rp>(</rp><rt>サタン</rt><rp>)</rp></ruby>と<ruby><rb>漢</rb><rt>かん</rt><rb>字</rb><rt>じ</rt></ruby><br />",
+            &mut hints,
+        );
+        assert_eq!(text, "魔王と漢字");
+        assert_eq!(hints["魔王"], "さたん");
+        assert_eq!(hints["漢"], "かん");
+        assert_eq!(hints["字"], "じ");
+    }
+
+    #[test]
+    fn test_parse_html_ruby_attributes() {
+        let mut hints = ReadingHints::new();
+        let text = parse_html_ruby(
+            "<span class=\"x\"><ruby class=\"r\">竈門<rp class=\"p\">(</rp><rt class=\"t\">かまど</rt><rp class=\"p\">)</rp></ruby>家</span>",
+            &mut hints,
+        );
+        assert_eq!(text, "竈門家");
+        assert_eq!(hints["竈門"], "かまど");
+        assert_eq!(hints.len(), 1);
+    }
+
+    #[test]
+    fn test_read_shift_jis() {
+        let dir = std::env::temp_dir().join(format!(
+            "gd2anki_test_read_shift_jis_{}",
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("novel.txt");
+        // ｜竈門《かまど》の家に生《う》まれた。 as Aozora Bunko writes it
+        fs::write(
+            &path,
+            b"\x81\x62\xe2\x7d\x96\xe5\x81\x73\x82\xa9\x82\xdc\x82\xc7\x81\x74\x82\xcc\x89\xc6\x82\xc9\x90\xb6\x81\x73\x82\xa4\x81\x74\x82\xdc\x82\xea\x82\xbd\x81\x42",
+        )
+        .unwrap();
+
+        let (sentences, hints) = read_ruby_text(path.to_str().unwrap()).unwrap();
+        assert_eq!(sentences, vec!["竈門の家に生まれた。"]);
+        assert_eq!(hints["竈門"], "かまど");
+        assert_eq!(hints["生ま"], "うま");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_split_sentences() {
+        assert_eq!(
+            split_sentences("「行くぞ！」と言った。本当？！　はい"),
+            vec!["「行くぞ！」と言った。", "本当？！", "はい"]
+        );
+    }
+
+    #[test]
+    fn test_hinted_reading() {
+        let mut hints = ReadingHints::new();
+        parse_ruby(
+            "道を尋《たず》ねた。魔王《さたん》が生《う》まれる。",
+            &mut hints,
+        );
+        assert_eq!(hints["尋ね"], "たずね");
+        assert_eq!(hints["魔王"], "さたん");
+        assert_eq!(hints.len(), 3);
+        assert_eq!(
+            hinted_reading("尋ねた", &hints),
+            Some("たずねた".to_string())
+        );
+        assert_eq!(
+            hinted_reading("生まれる", &hints),
+            Some("うまれる".to_string())
+        );
+        assert_eq!(hinted_reading("生きる", &hints), None);
+        assert_eq!(hinted_reading("生", &hints), None);
+        assert_eq!(hinted_reading("魔王", &hints), Some("さたん".to_string()));
+        assert_eq!(hinted_reading("魔王城", &hints), None);
+        assert_eq!(hinted_reading("ねた", &hints), None);
+    }
+}
@@ -0,0 +1,274 @@
+use regex::Regex;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::LazyLock;
+
+use crate::error::{Error, Result};
+use crate::gdict::Vocab;
+use crate::pairing::{find_containing, read_numbered, write_numbered, Decision, Pairing};
+
+/// Where a sentence is said in a media file, in milliseconds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cue {
+    pub file: String,
+    pub start: u64,
+    pub end: u64,
+}
+
+/// One line of a subtitle file, its markup removed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubtitleLine {
+    pub start: u64,
+    pub end: u64,
+    pub text: String,
+}
+
+/// Parses `01:02:03,456`, `02:03.456` or the `1:02:03.45` of ass into
+/// milliseconds.
+pub fn parse_timestamp(s: &str) -> Option<u64> {
+    let s = s.trim().replace(',', ".");
+    let (clock, fraction) = match s.split_once('.') {
+        Some((clock, fraction)) => (clock, fraction),
+        None => (s.as_str(), "0"),
+    };
+
+    let mut seconds = 0;
+    for part in clock.split(':') {
+        seconds = seconds * 60 + part.parse::<u64>().ok()?;
+    }
+    // .4 is 400ms, .45 is 450ms, .456 is 456ms
+    let fraction: String = fraction.chars().chain("000".chars()).take(3).collect();
+    Some(seconds * 1000 + fraction.parse::<u64>().ok()?)
+}
+
+static RE_MARKUP: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<[^>]*>|\{[^}]*\}").unwrap());
+
+/// Removes html tags, ass override blocks and line breaks.
+fn clean_text(text: &str) -> String {
+    RE_MARKUP
+        .replace_all(text, "")
+        .replace("\\N", "")
+        .replace("\\n", "")
+        .replace("\\h", " ")
+        .lines()
+        .map(str::trim)
+        .collect()
+}
+
+/// Parses srt and WebVTT, whose cues are blank line separated blocks with a
+/// `start --> end` line.
+pub fn parse_srt(text: &str) -> Vec<SubtitleLine> {
+    let mut lines = Vec::new();
+    for block in text.replace("\r\n", "\n").split("\n\n") {
+        let mut block_lines = block.lines().skip_while(|l| !l.contains("-->"));
+        let timing = match block_lines.next() {
+            Some(timing) => timing,
+            None => continue,
+        };
+        let (start, end) = match timing.split_once("-->") {
+            Some(x) => x,
+            None => continue,
+        };
+        // vtt may put cue settings after the end time
+        let end = end.split_whitespace().next().unwrap_or("");
+        let text = clean_text(&block_lines.collect::<Vec<_>>().join("\n"));
+        match (parse_timestamp(start), parse_timestamp(end)) {
+            (Some(start), Some(end)) if !text.is_empty() => {
+                lines.push(SubtitleLine { start, end, text })
+            }
+            _ => {}
+        }
+    }
+    lines
+}
+
+/// Parses the `Dialogue:` lines of the `[Events]` of an ass or ssa file.
+pub fn parse_ass(text: &str) -> Vec<SubtitleLine> {
+    // the default v4+ field order, until a Format: line says otherwise
+    let mut format: Vec<String> = [
+        "Layer", "Start", "End", "Style", "Name", "MarginL", "MarginR", "MarginV", "Effect", "Text",
+    ]
+    .iter()
+    .map(|f| f.to_string())
+    .collect();
+    let mut in_events = false;
+    let mut lines = Vec::new();
+
+    for line in text.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_events = line.eq_ignore_ascii_case("[events]");
+        } else if !in_events {
+            continue;
+        } else if let Some(fields) = line.strip_prefix("Format:") {
+            format = fields.split(',').map(|f| f.trim().to_string()).collect();
+        } else if let Some(fields) = line.strip_prefix("Dialogue:") {
+            // the text is last and may contain commas
+            let fields: Vec<&str> = fields.splitn(format.len(), ',').collect();
+            let field = |name: &str| {
+                format
+                    .iter()
+                    .position(|f| f == name)
+                    .and_then(|i| fields.get(i))
+                    .copied()
+            };
+            let text = clean_text(field("Text").unwrap_or(""));
+            match (
+                field("Start").and_then(parse_timestamp),
+                field("End").and_then(parse_timestamp),
+            ) {
+                (Some(start), Some(end)) if !text.is_empty() => {
+                    lines.push(SubtitleLine { start, end, text })
+                }
+                _ => {}
+            }
+        }
+    }
+    lines
+}
+
+/// Reads a subtitle file, picking the parser from its extension.
+pub fn read_subtitles(path: &str) -> Result<Vec<SubtitleLine>> {
+    let text = fs::read_to_string(path).map_err(Error::io(path))?;
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    Ok(match extension.as_str() {
+        "ass" | "ssa" => parse_ass(&text),
+        _ => parse_srt(text.trim_start_matches('\u{feff}')),
+    })
+}
+
+/// Pairs each of `words` with the first subtitle line containing it, and
+/// returns the cue of every sentence by its number.
+pub fn pair_subtitles(
+    lines: &[SubtitleLine],
+    file: &str,
+    words: &[String],
+) -> (Pairing, HashMap<usize, Cue>) {
+    let mut pairing = Pairing::default();
+    let mut cues = HashMap::new();
+
+    for word in words.iter().map(|w| w.trim()).filter(|w| !w.is_empty()) {
+        let line = match find_containing(lines, word, |l| &l.text) {
+            Some(line) => line,
+            None => {
+                pairing.decisions.push(Decision::Dropped {
+                    word: word.to_string(),
+                    reason: "not in the subtitles".to_string(),
+                });
+                continue;
+            }
+        };
+        let vocab = Vocab {
+            kanji: word.to_string(),
+            sentence: line.text.clone(),
+        };
+        let reason = format!("said at {} in {}", format_timestamp(line.start), file);
+        let number = pairing.add_pair(&vocab, &reason);
+        cues.entry(number).or_insert_with(|| Cue {
+            file: file.to_string(),
+            start: line.start,
+            end: line.end,
+        });
+    }
+
+    (pairing, cues)
+}
+
+/// Formats milliseconds as `01:02:03.456`.
+pub fn format_timestamp(ms: u64) -> String {
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        ms % 1000
+    )
+}
+
+/// Writes the cues as `0012<TAB>start<TAB>end<TAB>file` lines.
+pub fn write_cues(path: &str, cues: &HashMap<usize, Cue>) -> Result<()> {
+    write_numbered(path, cues, |cue| {
+        vec![cue.start.to_string(), cue.end.to_string(), cue.file.clone()]
+    })
+}
+
+/// Reads the cues written by `write_cues`.
+pub fn read_cues(path: &str) -> Result<HashMap<usize, Cue>> {
+    read_numbered(path, 3, "cue", |v| {
+        Some(Cue {
+            start: v[0].parse().ok()?,
+            end: v[1].parse().ok()?,
+            file: v[2].to_string(),
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(parse_timestamp("00:01:02,345"), Some(62345));
+        assert_eq!(parse_timestamp("01:02.5"), Some(62500));
+        assert_eq!(parse_timestamp("0:00:01.45"), Some(1450));
+        assert_eq!(parse_timestamp("1:0x"), None);
+        assert_eq!(format_timestamp(3_723_456), "01:02:03.456");
+    }
+
+    #[test]
+    fn test_parse_srt() {
+        let srt = "1\r\n00:00:01,000 --> 00:00:02,500\r\n<i>彼は駅で</i>\r\n友達に会った\r\n\r\n2\r\n00:00:03,000 --> 00:00:04,000\r\nはい\r\n";
+        let lines = parse_srt(srt);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].text, "彼は駅で友達に会った");
+        assert_eq!(lines[0].start, 1000);
+        assert_eq!(lines[0].end, 2500);
+    }
+
+    #[test]
+    fn test_parse_vtt() {
+        let vtt =
+            "WEBVTT\n\nNOTE a comment\n\nintro\n00:01.000 --> 00:02.000 align:start\n道を尋ねた\n";
+        let lines = parse_srt(vtt);
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].text, "道を尋ねた");
+        assert_eq!(lines[0].end, 2000);
+    }
+
+    #[test]
+    fn test_parse_ass() {
+        let ass = "[Script Info]\nTitle: x\n\n[Events]\nFormat: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\nDialogue: 0,0:00:01.50,0:00:03.00,Default,,0,0,0,,{\\an8}そうか、\\N道を尋ねた\nComment: 0,0:00:04.00,0:00:05.00,Default,,0,0,0,,note\n";
+        let lines = parse_ass(ass);
+        assert_eq!(
+            lines,
+            vec![SubtitleLine {
+                start: 1500,
+                end: 3000,
+                text: "そうか、道を尋ねた".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_pair_subtitles() {
+        let lines = parse_srt("1\n00:00:01,000 --> 00:00:02,000\n道を尋ねた\n\n2\n00:00:03,000 --> 00:00:04,000\n友達だ\n");
+        let words = vec!["友達".to_string(), "尋ねる".to_string(), "猫".to_string()];
+        let (pairing, cues) = pair_subtitles(&lines, "ep01.srt", &words);
+        assert_eq!(pairing.sentences, vec!["友達だ", "道を尋ねた"]);
+        assert_eq!(
+            pairing.vocabs,
+            vec![(0, "友達".to_string()), (1, "尋ねる".to_string())]
+        );
+        assert_eq!(cues[&1].start, 1000);
+        assert_eq!(cues[&0].file, "ep01.srt");
+        assert!(pairing
+            .decisions
+            .iter()
+            .any(|d| matches!(d, Decision::Dropped { word, .. } if word == "猫")));
+    }
+}
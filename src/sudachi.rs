@@ -0,0 +1,214 @@
+use log::warn;
+use serde::Deserialize;
+use std::fs;
+use std::process;
+
+use crate::analyzer::{Analyzer, Morpheme};
+use crate::error::{Error, Result};
+use crate::utils::katakana_to_hiragana;
+
+/// How finely Sudachi splits compounds: `A` gives the shortest units, `C`
+/// keeps e.g. 国家公務員 as one word.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SplitMode {
+    A,
+    B,
+    C,
+}
+
+impl SplitMode {
+    fn as_str(self) -> &'static str {
+        match self {
+            SplitMode::A => "A",
+            SplitMode::B => "B",
+            SplitMode::C => "C",
+        }
+    }
+}
+
+/// Sudachi, run through the `sudachi` binary of sudachi.rs on `PATH`.
+///
+/// `parse` reads both the tab separated output of `sudachi -a` (or
+/// `sudachipy tokenize -a`) and json lines with one array of morphemes per
+/// sentence, with the fields named after the SudachiPy morpheme methods.
+#[derive(Debug)]
+pub struct Sudachi {
+    pub split_mode: SplitMode,
+}
+
+impl Analyzer for Sudachi {
+    fn run(&self, sentences_path: &str, output_path: &str) -> Result<()> {
+        let status = process::Command::new("sudachi")
+            .arg("-m")
+            .arg(self.split_mode.as_str())
+            .arg("-a")
+            .arg("-o")
+            .arg(output_path)
+            .arg(sentences_path)
+            .status()
+            .map_err(Error::io("sudachi"))?;
+        if !status.success() {
+            return Err(Error::Command {
+                program: "sudachi".to_string(),
+                status: status.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    fn parse(&self, output_path: &str) -> Result<Vec<Vec<Morpheme>>> {
+        parse_sudachi_output(output_path)
+    }
+}
+
+/// One morpheme as serialized from the SudachiPy api.
+#[derive(Debug, Deserialize)]
+struct SudachiMorpheme {
+    surface: String,
+    part_of_speech: Vec<String>,
+    normalized_form: Option<String>,
+    dictionary_form: Option<String>,
+    reading_form: Option<String>,
+}
+
+fn make_morpheme(
+    surface: &str,
+    part_of_speech: &[&str],
+    normalized_form: Option<&str>,
+    dictionary_form: Option<&str>,
+    reading_form: Option<&str>,
+) -> Option<Morpheme> {
+    // 動詞,一般,*,*,下一段-ナ行,連用形-一般
+    if surface.is_empty() || part_of_speech.len() < 6 {
+        return None;
+    }
+    let reading = reading_form.filter(|r| !r.is_empty()).unwrap_or(surface);
+
+    Some(Morpheme {
+        surface: surface.to_string(),
+        reading: katakana_to_hiragana(reading),
+        lemma: dictionary_form
+            .or(normalized_form)
+            .unwrap_or(surface)
+            .to_string(),
+        pos: part_of_speech[0].to_string(),
+        sub_pos: part_of_speech[1].to_string(),
+        conjugation_type: part_of_speech[4].to_string(),
+        conjugation_form: part_of_speech[5].to_string(),
+        semantic_info: "*".to_string(),
+        normalized_form: normalized_form.map(str::to_string),
+        canonical_form: None,
+        alternatives: Vec::new(),
+    })
+}
+
+/// Parses one line of the tab separated Sudachi output, i.e. surface, part of
+/// speech, normalized form and, with `-a`, dictionary form and reading.
+pub fn parse_sudachi_line(line: &str) -> Option<Morpheme> {
+    let v: Vec<&str> = line.split('\t').collect();
+    if v.len() < 3 {
+        return None;
+    }
+    let part_of_speech: Vec<&str> = v[1].split(',').collect();
+
+    make_morpheme(
+        v[0],
+        &part_of_speech,
+        Some(v[2]),
+        v.get(3).copied(),
+        v.get(4).copied(),
+    )
+}
+
+/// Parses one json line of the Sudachi output, holding a whole sentence.
+pub fn parse_sudachi_json_line(line: &str) -> Option<Vec<Morpheme>> {
+    let morphemes: Vec<SudachiMorpheme> = serde_json::from_str(line).ok()?;
+    morphemes
+        .iter()
+        .map(|m| {
+            let part_of_speech: Vec<&str> = m.part_of_speech.iter().map(String::as_str).collect();
+            make_morpheme(
+                &m.surface,
+                &part_of_speech,
+                m.normalized_form.as_deref(),
+                m.dictionary_form.as_deref(),
+                m.reading_form.as_deref(),
+            )
+        })
+        .collect()
+}
+
+/// Reads the Sudachi output at `output_path`, one `Vec` per `EOS` or json
+/// line.
+pub fn parse_sudachi_output(output_path: &str) -> Result<Vec<Vec<Morpheme>>> {
+    let sudachi_output = fs::read_to_string(output_path).map_err(Error::io(output_path))?;
+
+    let mut sentences = Vec::new();
+    let mut sentence = Vec::new();
+
+    for (line, x) in sudachi_output.lines().enumerate() {
+        if x.starts_with('[') {
+            match parse_sudachi_json_line(x) {
+                Some(morphemes) => sentences.push(morphemes),
+                None => {
+                    warn!(
+                        "skipping {}",
+                        Error::line(output_path, line + 1, x, "not a json sentence")
+                    );
+                    // keep the sentence numbers in line with the sentence file
+                    sentences.push(Vec::new());
+                }
+            }
+        } else if x == "EOS" {
+            sentences.push(sentence);
+            sentence = Vec::new();
+        } else {
+            match parse_sudachi_line(x) {
+                Some(morpheme) => sentence.push(morpheme),
+                None => warn!(
+                    "skipping {}",
+                    Error::line(output_path, line + 1, x, "not a morpheme line")
+                ),
+            }
+        }
+    }
+
+    Ok(sentences)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_line() {
+        let m = parse_sudachi_line("附属\t名詞,普通名詞,サ変可能,*,*,*\t付属\t附属\tフゾク\t0\t[]")
+            .unwrap();
+        assert_eq!(m.surface, "附属");
+        assert_eq!(m.reading, "ふぞく");
+        assert_eq!(m.lemma, "附属");
+        assert_eq!(m.pos, "名詞");
+        assert_eq!(m.sub_pos, "普通名詞");
+        assert_eq!(m.normalized_form, Some("付属".to_string()));
+    }
+
+    #[test]
+    fn test_parse_line_without_all() {
+        let m = parse_sudachi_line("尋ね\t動詞,一般,*,*,下一段-ナ行,連用形-一般\t尋ねる").unwrap();
+        assert_eq!(m.reading, "尋ね");
+        assert_eq!(m.lemma, "尋ねる");
+        assert_eq!(m.conjugation_form, "連用形-一般");
+    }
+
+    #[test]
+    fn test_parse_json_line() {
+        let v = parse_sudachi_json_line(
+            r#"[{"surface": "国家公務員", "part_of_speech": ["名詞", "普通名詞", "一般", "*", "*", "*"], "normalized_form": "国家公務員", "dictionary_form": "国家公務員", "reading_form": "コッカコウムイン"}, {"surface": "だ", "part_of_speech": ["助動詞", "*", "*", "*", "助動詞-ダ", "終止形-一般"], "normalized_form": "だ", "dictionary_form": "だ", "reading_form": "ダ"}]"#,
+        )
+        .unwrap();
+        assert_eq!(v.len(), 2);
+        assert_eq!(v[0].surface, "国家公務員");
+        assert_eq!(v[0].reading, "こっかこうむいん");
+        assert_eq!(v[1].conjugation_type, "助動詞-ダ");
+    }
+}
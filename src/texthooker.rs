@@ -0,0 +1,213 @@
+use regex::Regex;
+use rusqlite::{params, Connection};
+use std::convert::TryFrom;
+use std::fs;
+use std::sync::LazyLock;
+
+use crate::error::{Error, Result};
+use crate::gdict::Vocab;
+use crate::pairing::{find_containing, Decision, Pairing};
+
+/// One text box of a Textractor or texthooker log.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogLine {
+    pub timestamp: Option<u64>, // unix seconds
+    pub text: String,
+}
+
+static RE_STAMP: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"^\[?(\d{4}-\d{2}-\d{2}[ T]\d{2}:\d{2}(?::\d{2}(?:\.\d+)?)?|\d{10}(?:\d{3})?)\]?[\t ]+",
+    )
+    .unwrap()
+});
+
+/// Splits the time off `[2026-10-01 18:00:00] text`,
+/// `2026-10-01T18:00:00<TAB>text` or `1790000000<TAB>text`. Lines of plain
+/// Textractor logs have none.
+fn split_timestamp(line: &str) -> (Option<&str>, &str) {
+    match RE_STAMP.captures(line) {
+        Some(caps) => (
+            Some(caps.get(1).unwrap().as_str()),
+            &line[caps.get(0).unwrap().end()..],
+        ),
+        None => (None, line),
+    }
+}
+
+/// Decodes a log, which Windows tools may well have written as UTF-16.
+fn decode_log(bytes: &[u8]) -> String {
+    let utf16 = |bytes: &[u8], from: fn([u8; 2]) -> u16| {
+        let units: Vec<u16> = bytes.chunks_exact(2).map(|c| from([c[0], c[1]])).collect();
+        String::from_utf16_lossy(&units)
+    };
+    match bytes {
+        [0xff, 0xfe, rest @ ..] => utf16(rest, u16::from_le_bytes),
+        [0xfe, 0xff, rest @ ..] => utf16(rest, u16::from_be_bytes),
+        [0xef, 0xbb, 0xbf, rest @ ..] => String::from_utf8_lossy(rest).into_owned(),
+        _ => String::from_utf8_lossy(bytes).into_owned(),
+    }
+}
+
+/// Reads the lines of a log, skipping blank lines and the repeats of a line
+/// hooked twice. Dates are taken as local time, as the hookers write them.
+pub fn parse_log(text: &str) -> Result<Vec<LogLine>> {
+    // sqlite already knows the local time zone, see Ledger::timestamp
+    let conn = Connection::open_in_memory().map_err(Error::database(":memory:"))?;
+    let to_unix = |stamp: &str| -> Result<Option<u64>> {
+        if stamp.bytes().all(|b| b.is_ascii_digit()) {
+            let n: u64 = stamp.parse().unwrap_or_default();
+            // milliseconds have 13 digits
+            return Ok(Some(if stamp.len() > 10 { n / 1000 } else { n }));
+        }
+        conn.query_row(
+            "SELECT CAST(strftime('%s', ?1, 'utc') AS INTEGER)",
+            params![stamp],
+            |row| row.get::<_, Option<i64>>(0),
+        )
+        .map(|t| t.and_then(|t| u64::try_from(t).ok()))
+        .map_err(Error::database(":memory:"))
+    };
+
+    let mut lines: Vec<LogLine> = Vec::new();
+    for line in text.lines() {
+        let (stamp, text) = split_timestamp(line.trim());
+        let text = text.trim();
+        if text.is_empty() || lines.last().is_some_and(|last| last.text == text) {
+            continue;
+        }
+        lines.push(LogLine {
+            timestamp: match stamp {
+                Some(stamp) => to_unix(stamp)?,
+                None => None,
+            },
+            text: text.to_string(),
+        });
+    }
+    Ok(lines)
+}
+
+/// Reads the log at `path`.
+pub fn read_log(path: &str) -> Result<Vec<LogLine>> {
+    parse_log(&decode_log(&fs::read(path).map_err(Error::io(path))?))
+}
+
+fn line_text<'a>(item: &'a (usize, &LogLine)) -> &'a str {
+    &item.1.text
+}
+
+/// Pairs each lookup with a line of the log. When both have times, that is
+/// the nearest line containing the word shown before the lookup; otherwise
+/// the next line containing it after the line of the previous lookup, or
+/// else the nearest one before it.
+pub fn pair_log(lines: &[LogLine], file: &str, lookups: &[(String, Option<u64>)]) -> Pairing {
+    let timed = !lines.is_empty() && lines.iter().all(|l| l.timestamp.is_some());
+    let mut pairing = Pairing::default();
+    let mut cursor = 0;
+
+    for (word, time) in lookups {
+        let word = word.trim();
+        if word.is_empty() {
+            continue;
+        }
+        let by_time = time.filter(|_| timed).and_then(|time| {
+            let shown = lines.partition_point(|l| l.timestamp <= Some(time));
+            find_containing(lines[..shown].iter().enumerate().rev(), word, line_text)
+        });
+        let found = by_time
+            .or_else(|| find_containing(lines.iter().enumerate().skip(cursor), word, line_text))
+            .or_else(|| find_containing(lines[..cursor].iter().enumerate().rev(), word, line_text));
+
+        match found {
+            Some((i, line)) => {
+                cursor = i;
+                let vocab = Vocab {
+                    kanji: word.to_string(),
+                    sentence: line.text.clone(),
+                };
+                pairing.add_pair(&vocab, &format!("line {} of {}", i + 1, file));
+            }
+            None => pairing.decisions.push(Decision::Dropped {
+                word: word.to_string(),
+                reason: format!("not in {}", file),
+            }),
+        }
+    }
+    pairing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookups(v: &[(&str, Option<u64>)]) -> Vec<(String, Option<u64>)> {
+        v.iter().map(|(w, t)| (w.to_string(), *t)).collect()
+    }
+
+    #[test]
+    fn test_parse_log() {
+        let lines = parse_log(
+            "1790000000\t友達に会った\n\n[1790000060123] 友達に会った\n1790000120 道を尋ねた\n",
+        )
+        .unwrap();
+        assert_eq!(
+            lines,
+            vec![
+                LogLine {
+                    timestamp: Some(1_790_000_000),
+                    text: "友達に会った".to_string()
+                },
+                LogLine {
+                    timestamp: Some(1_790_000_120),
+                    text: "道を尋ねた".to_string()
+                },
+            ]
+        );
+        let lines = parse_log("[2026-10-01 18:00:00] 道\n「はい」\n").unwrap();
+        assert!(lines[0].timestamp.is_some());
+        assert_eq!(lines[1].timestamp, None);
+        assert_eq!(lines[1].text, "「はい」");
+    }
+
+    #[test]
+    fn test_decode_utf16() {
+        let mut bytes = vec![0xff, 0xfe];
+        bytes.extend("道\r\n".encode_utf16().flat_map(u16::to_le_bytes));
+        assert_eq!(decode_log(&bytes), "道\r\n");
+    }
+
+    #[test]
+    fn test_pair_log_by_time() {
+        let lines = parse_log(
+            "1790000100 友達に会った\n1790000200 道を尋ねた\n1790000300 友達と道を歩いた\n",
+        )
+        .unwrap();
+        let pairing = pair_log(
+            &lines,
+            "vn.log",
+            &lookups(&[
+                ("友達", Some(1_790_000_250)),
+                ("尋ねる", Some(1_790_000_350)),
+                ("歩く", Some(1_790_000_050)),
+            ]),
+        );
+        assert_eq!(
+            pairing.sentences,
+            vec!["友達に会った", "道を尋ねた", "友達と道を歩いた"]
+        );
+    }
+
+    #[test]
+    fn test_pair_log_by_content() {
+        let lines = parse_log("友達に会った\n道を尋ねた\n友達と道を歩いた\n").unwrap();
+        let pairing = pair_log(
+            &lines,
+            "vn.log",
+            &lookups(&[("尋ねる", None), ("友達", None), ("猫", None)]),
+        );
+        assert_eq!(pairing.sentences, vec!["道を尋ねた", "友達と道を歩いた"]);
+        assert!(
+            matches!(&pairing.decisions[..], [.., Decision::Dropped { word, .. }] if word == "猫")
+        );
+    }
+}
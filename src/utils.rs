@@ -1,10 +1,12 @@
-const SMALL_HIRAGANA: [char; 10] = ['ゃ', 'ゅ', 'ょ', 'ぁ', 'ぃ', 'ぅ', 'ぇ', 'ぉ', 'ゎ', 'っ'];
+pub const SMALL_HIRAGANA: [char; 10] = ['ゃ', 'ゅ', 'ょ', 'ぁ', 'ぃ', 'ぅ', 'ぇ', 'ぉ', 'ゎ', 'っ'];
 
-fn is_normal_hiragana(c: &char) -> bool {
-    !SMALL_HIRAGANA.contains(&c)
+/// Whether `c` counts as a mora on its own, i.e. is not a small kana.
+pub fn is_normal_hiragana(c: &char) -> bool {
+    !SMALL_HIRAGANA.contains(c)
 }
 
-fn mora_len(hiragana_word: &str) -> usize {
+/// Number of morae in a hiragana word.
+pub fn mora_len(hiragana_word: &str) -> usize {
     let len = hiragana_word
         .chars()
         .filter(is_normal_hiragana)
@@ -14,7 +16,9 @@ fn mora_len(hiragana_word: &str) -> usize {
     len
 }
 
-fn split_to_mora(hiranga_word: &str) -> Vec<String> {
+/// Splits a hiragana word into its morae, keeping small kana with the
+/// preceding character.
+pub fn split_to_mora(hiranga_word: &str) -> Vec<String> {
     let v: Vec<char> = hiranga_word.chars().collect();
     let mut ret_vec: Vec<String> = Vec::new();
     for (i, x) in v.clone().iter().enumerate() {
@@ -29,11 +33,22 @@ fn split_to_mora(hiranga_word: &str) -> Vec<String> {
     ret_vec
 }
 
+/// Converts the katakana of `word` to hiragana, leaving everything else as is.
+pub fn katakana_to_hiragana(word: &str) -> String {
+    word.chars()
+        .map(|c| match c {
+            'ァ'..='ヶ' => std::char::from_u32(c as u32 - 0x60).unwrap_or(c),
+            _ => c,
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_small_hiragana_filter() {
         assert_eq!(is_normal_hiragana(&'ゃ'), false);
         assert_eq!(is_normal_hiragana(&'ゎ'), false);
@@ -67,4 +82,12 @@ mod tests {
             vec!["し", "ん", "ぎょ", "う", "そ", "う"]
         );
     }
+
+    #[test]
+    fn test_katakana_to_hiragana() {
+        assert_eq!(katakana_to_hiragana("タズネテ"), "たずねて");
+        assert_eq!(katakana_to_hiragana("ヴァイオリン"), "ゔぁいおりん");
+        assert_eq!(katakana_to_hiragana("コーヒー。"), "こーひー。");
+        assert_eq!(katakana_to_hiragana("ともだち"), "ともだち");
+    }
 }